@@ -68,18 +68,19 @@ fn main() -> ! {
     let mut cl_in : LightCliInput<U32> = LightCliInput::new();
     let mut cl_out = LightCliOutput::new(&mut tx);
 
-    writeln!(cl_out, "Commands:").unwrap();
-    writeln!(cl_out, "   - HELLO Name=<Name>: Set the name").unwrap();
-    writeln!(cl_out, "   - EHLO: Print the name").unwrap();
+    writeln!(cl_out, "Type HELP for a list of commands").unwrap();
 
     loop {
         let _ = cl_out.flush();
         let _ = cl_in.fill(&mut rx);
 
         lightcli!(cl_in, cl_out, cmd, key, val, [
+                /// Set the name
                 "HELLO" => [
+                    /// The new name
                     "Name" => name = String::from(val)
                 ] => { writeln!(cl_out, "Name set").unwrap(); };
+                /// Print the name
                 "EHLO" => [
                 ] => { writeln!(cl_out, "EHLO Name={}", name.as_str()).unwrap(); }
             ]
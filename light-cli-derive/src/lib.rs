@@ -0,0 +1,268 @@
+//! Derive macro for the `LightCommand` trait of [`light-cli`].
+//! 
+//! Use it through the `derive` feature of `light-cli`, which reexports the
+//! macro next to the trait.
+//! 
+//! [`light-cli`]: https://rudihorn.github.io/light-cli/light_cli/
+
+extern crate proc_macro;
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{format_ident, quote};
+use syn::{parse_macro_input, Attribute, Data, DeriveInput, Error, Fields, Ident, Lit, Meta, Type};
+
+/// A command, which is either a variant of an enum or a struct.
+struct CommandDef<'a> {
+    name: String,
+    description: String,
+    /// The path used to construct the command, e.g. `Cmd::Hello`.
+    path: TokenStream2,
+    /// `None` for unit variants and structs.
+    fields: Option<Vec<KeyDef<'a>>>,
+}
+
+impl<'a> CommandDef<'a> {
+    fn keys(&self) -> &[KeyDef<'a>] {
+        self.fields.as_deref().unwrap_or(&[])
+    }
+}
+
+/// A key of a command, given by a named field.
+struct KeyDef<'a> {
+    ident: &'a Ident,
+    name: String,
+    description: String,
+    /// The type the value is parsed into, `T` for fields of type `Option<T>`.
+    ty: &'a Type,
+    required: bool,
+    /// Whether the field is a `bool`, which is `false` unless given.
+    flag: bool,
+    /// The field of the builder collecting the value.
+    slot: Ident,
+}
+
+/// Implements `LightCommand` for an enum or struct with named fields, see the
+/// documentation of the trait in `light-cli`.
+#[proc_macro_derive(LightCommand)]
+pub fn derive_light_command(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+
+    match expand(&input) {
+        Ok(tokens) => tokens.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+/// Concatenate the doc comments in `attrs`, as `concat!` does in `lightcli!`.
+fn description(attrs: &[Attribute]) -> String {
+    attrs.iter()
+        .filter(|attr| attr.path.is_ident("doc"))
+        .filter_map(|attr| match attr.parse_meta() {
+            Ok(Meta::NameValue(meta)) => match meta.lit {
+                Lit::Str(s) => Some(s.value()),
+                _ => None,
+            },
+            _ => None,
+        })
+        .collect()
+}
+
+/// The `T` of a field of type `Option<T>`.
+fn option_inner(ty: &Type) -> Option<&Type> {
+    let path = match ty {
+        Type::Path(ty) if ty.qself.is_none() => &ty.path,
+        _ => return None,
+    };
+    let segment = path.segments.last()?;
+    if segment.ident != "Option" {
+        return None;
+    }
+    match &segment.arguments {
+        syn::PathArguments::AngleBracketed(args) if args.args.len() == 1 => match &args.args[0] {
+            syn::GenericArgument::Type(ty) => Some(ty),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Whether `ty` is `bool`.
+fn is_bool(ty: &Type) -> bool {
+    match ty {
+        Type::Path(ty) if ty.qself.is_none() => ty.path.is_ident("bool"),
+        _ => false,
+    }
+}
+
+/// Collect the command named `ident` from its fields.
+fn command<'a>(index: usize, ident: &Ident, attrs: &[Attribute], path: TokenStream2,
+    fields: &'a Fields) -> Result<CommandDef<'a>, Error> {
+    let fields = match fields {
+        Fields::Named(fields) => Some(fields.named.iter().map(|field| {
+            let ident = field.ident.as_ref().unwrap();
+            let name = ident.to_string().trim_start_matches("r#").to_string();
+            let flag = is_bool(&field.ty);
+            let (ty, required) = match option_inner(&field.ty) {
+                Some(ty) => (ty, false),
+                None => (&field.ty, !flag),
+            };
+            KeyDef {
+                ident,
+                slot: format_ident!("c{}_{}", index, name),
+                name,
+                description: description(&field.attrs),
+                ty,
+                required,
+                flag,
+            }
+        }).collect()),
+        Fields::Unit => None,
+        Fields::Unnamed(fields) =>
+            return Err(Error::new_spanned(fields, "LightCommand does not support tuple fields")),
+    };
+
+    Ok(CommandDef {
+        name: ident.to_string().to_uppercase(),
+        description: description(attrs),
+        path,
+        fields,
+    })
+}
+
+fn expand(input: &DeriveInput) -> Result<TokenStream2, Error> {
+    let ident = &input.ident;
+
+    if !input.generics.params.is_empty() {
+        return Err(Error::new_spanned(&input.generics, "LightCommand does not support generics"));
+    }
+
+    let commands = match &input.data {
+        Data::Enum(data) => data.variants.iter().enumerate().map(|(index, variant)| {
+            let variant_ident = &variant.ident;
+            command(index, variant_ident, &variant.attrs, quote!(#ident::#variant_ident), &variant.fields)
+        }).collect::<Result<Vec<_>, _>>()?,
+        Data::Struct(data) => vec![command(0, ident, &input.attrs, quote!(#ident), &data.fields)?],
+        Data::Union(_) => return Err(Error::new_spanned(input, "LightCommand does not support unions")),
+    };
+
+    let all_keys = commands.iter().flat_map(|command| command.keys()).collect::<Vec<_>>();
+
+    let slots = all_keys.iter().map(|key| &key.slot);
+    let slot_types = all_keys.iter().map(|key| key.ty);
+
+    let infos = commands.iter().map(|command| {
+        let name = &command.name;
+        let description = &command.description;
+        let key_infos = command.keys().iter().map(|key| {
+            let name = &key.name;
+            let description = &key.description;
+            let required = key.required;
+            quote! {
+                ::light_cli::KeyInfo { name: #name, description: #description, required: #required }
+            }
+        });
+        quote! {
+            ::light_cli::CommandInfo {
+                name: #name,
+                description: #description,
+                keys: &[ #( #key_infos ),* ],
+                arguments: &[],
+                subcommands: &[],
+            }
+        }
+    });
+
+    let attributes = commands.iter().flat_map(|command| {
+        let cmd = &command.name;
+        command.keys().iter().map(move |key| {
+            let name = &key.name;
+            let slot = &key.slot;
+            let ty = key.ty;
+            quote! {
+                (#cmd, #name) => builder.#slot = Some(
+                    <#ty as ::light_cli::ParseValue>::parse_value(val).ok_or(::light_cli::KeyError::Invalid)?),
+            }
+        })
+    });
+
+    let flags = commands.iter().flat_map(|command| {
+        let cmd = &command.name;
+        command.keys().iter().map(move |key| {
+            let name = &key.name;
+            let slot = &key.slot;
+            let ty = key.ty;
+            quote! {
+                (#cmd, #name) => builder.#slot = Some(
+                    <#ty as ::light_cli::ParseValue>::from_flag().ok_or(::light_cli::KeyError::Invalid)?),
+            }
+        })
+    });
+
+    let builds = commands.iter().map(|command| {
+        let cmd = &command.name;
+        let path = &command.path;
+        match &command.fields {
+            None => quote! { #cmd => Some(#path), },
+            Some(fields) => {
+                let values = fields.iter().map(|key| {
+                    let ident = key.ident;
+                    let slot = &key.slot;
+                    if key.required {
+                        quote! { #ident: builder.#slot? }
+                    } else if key.flag {
+                        quote! { #ident: builder.#slot.unwrap_or(false) }
+                    } else {
+                        quote! { #ident: builder.#slot }
+                    }
+                });
+                quote! { #cmd => Some(#path { #( #values ),* }), }
+            },
+        }
+    });
+
+    let builder = format_ident!("__{}LightCommandBuilder", ident);
+
+    Ok(quote! {
+        #[doc(hidden)]
+        #[derive(Default)]
+        #[allow(non_camel_case_types)]
+        pub struct #builder {
+            #( #slots: Option<#slot_types>, )*
+        }
+
+        impl ::light_cli::LightCommand for #ident {
+            type Builder = #builder;
+
+            const COMMANDS: &'static [::light_cli::CommandInfo] = &[ #( #infos ),* ];
+
+            #[allow(unused_variables, unreachable_code)]
+            fn on_attribute(builder: &mut Self::Builder, cmd: &str, key: &str, val: &str)
+                -> Result<(), ::light_cli::KeyError> {
+                match (cmd, key) {
+                    #( #attributes )*
+                    _ => return Err(::light_cli::KeyError::Unknown),
+                }
+                Ok(())
+            }
+
+            #[allow(unused_variables, unreachable_code)]
+            fn on_argument(builder: &mut Self::Builder, cmd: &str, index: usize, word: &str)
+                -> Result<(), ::light_cli::KeyError> {
+                match (cmd, word) {
+                    #( #flags )*
+                    _ => return Err(::light_cli::KeyError::Unknown),
+                }
+                Ok(())
+            }
+
+            #[allow(unused_variables)]
+            fn build(builder: Self::Builder, cmd: &str) -> Option<Self> {
+                match cmd {
+                    #( #builds )*
+                    _ => None,
+                }
+            }
+        }
+    })
+}
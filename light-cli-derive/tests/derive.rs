@@ -0,0 +1,147 @@
+use core::fmt::Write;
+
+use embedded_hal::serial;
+use heapless::consts::*;
+use heapless::spsc::Queue;
+use heapless::String;
+use light_cli::{CommandParser, LightCliInput, LightCliOutput, LightCommand};
+
+#[derive(Debug)]
+pub enum Error {
+    None,
+}
+
+pub struct SerialBufferDevice {
+    rb: Queue<u8, U512>,
+}
+
+impl serial::Read<u8> for SerialBufferDevice {
+    type Error = Error;
+
+    fn read(&mut self) -> nb::Result<u8, Self::Error> {
+        self.rb.dequeue().ok_or(nb::Error::WouldBlock)
+    }
+}
+
+pub struct SerialOutputDevice {
+    rb: Queue<u8, U512>,
+}
+
+impl serial::Write<u8> for SerialOutputDevice {
+    type Error = Error;
+
+    fn write(&mut self, word: u8) -> nb::Result<(), Self::Error> {
+        self.rb.enqueue(word).map_err(|_| nb::Error::Other(Error::None))
+    }
+
+    fn flush(&mut self) -> nb::Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+impl SerialOutputDevice {
+    fn read_str(&mut self) -> std::string::String {
+        let mut s = std::string::String::new();
+        while let Some(b) = self.rb.dequeue() {
+            s.push(b as char);
+        }
+        s
+    }
+}
+
+#[derive(Debug, PartialEq, LightCommand)]
+enum Cmd {
+    /// Set the name
+    Hello {
+        /// The new name
+        name: String<U8>,
+    },
+    /// Print the name
+    Ehlo,
+    /// Switch an LED
+    Led {
+        index: u8,
+        on: bool,
+        level: Option<u16>,
+    },
+}
+
+#[derive(Debug, PartialEq, LightCommand)]
+struct SetIp {
+    addr: String<U16>,
+}
+
+#[test]
+fn test_derive_enum() {
+    let mut sb = SerialBufferDevice { rb: Queue::new() };
+    let mut dev = SerialOutputDevice { rb: Queue::new() };
+    let mut cli: LightCliInput<U32, U256> = LightCliInput::new();
+    let mut parser: CommandParser<Cmd> = CommandParser::new();
+    let mut commands = Vec::new();
+
+    for b in concat!(
+        "HELLO name=Johnson\n",
+        "EHLO\n",
+        "LED index=3 on\n",
+        "LED index=4 on=off level=0x100\n",
+        "LED index=5\n",
+        "LED index=300 on\n",
+        "LED on\n",
+        "LED index=1 on state=1\n",
+        "HELLO name=VeryLongName\n",
+        "BYE\n",
+        "HELP\n",
+        "HELP HELLO\n",
+        "HELP ehlo\n").bytes() {
+        sb.rb.enqueue(b).unwrap();
+    }
+    cli.fill(&mut sb).unwrap();
+    cli.set_ignore_case(true);
+
+    {
+        let mut cl_out = LightCliOutput::new(&mut dev);
+        let _ = parser.parse(&mut cli, &mut cl_out, |cmd, out| {
+            writeln!(out, "OK").unwrap();
+            commands.push(cmd);
+        });
+        cl_out.flush().unwrap();
+    }
+
+    assert_eq!(commands, vec![
+        Cmd::Hello { name: String::from("Johnson") },
+        Cmd::Ehlo,
+        Cmd::Led { index: 3, on: true, level: None },
+        Cmd::Led { index: 4, on: false, level: Some(256) },
+        Cmd::Led { index: 5, on: false, level: None },
+    ]);
+
+    assert_eq!(dev.read_str(), concat!(
+        "OK\n",
+        "OK\n",
+        "OK\n",
+        "OK\n",
+        "OK\n",
+        "Invalid value for key index: 300\n",
+        "Missing key for command LED: index\n",
+        "Unknown key for command LED: state\n",
+        "Invalid value for key name: VeryLongName\n",
+        "Unknown command: BYE\n",
+        "Commands:\n",
+        "  HELLO  Set the name\n",
+        "  EHLO   Print the name\n",
+        "  LED    Switch an LED\n",
+        "HELLO: Set the name\n",
+        "  name  The new name (required)\n",
+        "EHLO: Print the name\n"));
+}
+
+#[test]
+fn test_derive_struct() {
+    assert_eq!(SetIp::COMMANDS.len(), 1);
+    assert_eq!(SetIp::COMMANDS[0].name, "SETIP");
+    assert_eq!(SetIp::COMMANDS[0].keys[0].name, "addr");
+
+    let mut builder = Default::default();
+    SetIp::on_attribute(&mut builder, "SETIP", "addr", "10.0.0.1").unwrap();
+    assert_eq!(SetIp::build(builder, "SETIP"), Some(SetIp { addr: String::from("10.0.0.1") }));
+}
@@ -0,0 +1,318 @@
+use core::fmt::{self, Write};
+use core::ptr;
+
+use heapless::ArrayLength;
+use heapless::consts::*;
+use nb;
+
+use input::LightCliInput;
+use lexer::CallbackCommand;
+use output::LightCliOutput;
+use registry::{CommandInfo, KeyInfo};
+use tokenizer;
+
+/// The reason a key or positional argument has been rejected by a [`Command`].
+/// 
+/// [`Command`]: trait.Command.html
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum KeyError {
+    /// The key or positional argument is not accepted by the command.
+    Unknown,
+    /// The value could not be used for the key.
+    Invalid,
+}
+
+/// A command which can be registered with a [`Dispatcher`], as an alternative
+/// to defining it in the table of the [`lightcli!`] macro.
+/// 
+/// # Type Parameters
+/// * `E` - The error type of the serial output.
+/// * `OLEN` - The buffer size of the output, 128 by default.
+/// 
+/// [`Dispatcher`]: struct.Dispatcher.html
+/// [`lightcli!`]: macro.lightcli.html
+pub trait Command<E, OLEN = U128> where OLEN: ArrayLength<u8> {
+    /// The name of the command, e.g. `I2C`.
+    fn name(&self) -> &'static str;
+
+    /// The description of the command, which may be empty.
+    fn description(&self) -> &'static str {
+        ""
+    }
+
+    /// The keys accepted by the command. Required keys which are missing and
+    /// repeated keys are reported by the [`Dispatcher`].
+    /// 
+    /// [`Dispatcher`]: struct.Dispatcher.html
+    fn keys(&self) -> &'static [KeyInfo] {
+        &[]
+    }
+
+    /// The positional arguments accepted by the command, named by their index
+    /// in brackets, e.g. `[0]`.
+    fn arguments(&self) -> &'static [KeyInfo] {
+        &[]
+    }
+
+    /// Called for every key value pair given for the command.
+    fn on_attribute(&mut self, key: &str, val: &str) -> Result<(), KeyError>;
+
+    /// Called for every word without a value given for the command, where
+    /// `index` counts the positional arguments from zero. Flags which match 
+    /// one of the [`keys`] do not count towards the index. By default they are
+    /// passed to [`on_attribute`] with an empty value and all other words are
+    /// unknown.
+    /// 
+    /// [`keys`]: trait.Command.html#method.keys
+    /// [`on_attribute`]: trait.Command.html#tymethod.on_attribute
+    fn on_argument(&mut self, index: usize, word: &str) -> Result<(), KeyError> {
+        let _ = index;
+        if self.keys().iter().any(|key| key.name == word) {
+            self.on_attribute(word, "")
+        } else {
+            Err(KeyError::Unknown)
+        }
+    }
+
+    /// Called at the end of the line to execute the command.
+    fn on_complete(&mut self, out: &mut LightCliOutput<E, OLEN>);
+
+    /// Called instead of [`on_complete`] at the end of a line with an error,
+    /// such as an invalid value or a missing key, so that the values collected
+    /// from the line can be discarded. Unknown keys and arguments are reported
+    /// without aborting the command. Does nothing by default.
+    /// 
+    /// [`on_complete`]: trait.Command.html#tymethod.on_complete
+    fn on_abort(&mut self) {}
+
+    /// Describe the command for the validation of its keys.
+    fn info(&self) -> CommandInfo {
+        CommandInfo {
+            name: self.name(),
+            description: self.description(),
+            keys: self.keys(),
+            arguments: self.arguments(),
+            subcommands: &[],
+        }
+    }
+}
+
+/// Routes the parsed input to the registered [`Command`] handlers and reports
+/// errors to the output in the same way as [`lightcli!`].
+/// 
+/// [`Command`]: trait.Command.html
+/// [`lightcli!`]: macro.lightcli.html
+pub struct Dispatcher<'a, E: 'a, OLEN: 'a = U128> where OLEN: ArrayLength<u8> {
+    commands: &'a mut [&'a mut dyn Command<E, OLEN>],
+    /// The descriptions of the commands in the same order.
+    table: &'a [CommandInfo],
+    /// Whether a value of the current line has been rejected as invalid.
+    failed: bool,
+}
+
+impl<'a, E, OLEN> Dispatcher<'a, E, OLEN> where OLEN: ArrayLength<u8> {
+    /// Create a dispatcher for the given commands.
+    /// 
+    /// # Arguments
+    /// * `commands` - The commands to pass the input on to.
+    /// * `table` - The storage for the descriptions of the commands, which needs
+    ///   an entry for every command, e.g. `[CommandInfo::default(); 2]` for two
+    ///   commands. Commands without an entry are treated as unknown.
+    pub fn new(commands: &'a mut [&'a mut dyn Command<E, OLEN>], table: &'a mut [CommandInfo]) -> Self {
+        let len = commands.len().min(table.len());
+        for (info, command) in table.iter_mut().zip(commands.iter()) {
+            *info = command.info();
+        }
+
+        let table : &'a [CommandInfo] = table;
+        Self {
+            commands,
+            table: &table[..len],
+            failed: false,
+        }
+    }
+
+    /// Parse the buffered data of `cli` and pass it on to the commands.
+    /// 
+    /// # Arguments
+    /// * `cli` - The input to parse data from.
+    /// * `out` - The output to pass to the commands and to write errors to.
+    /// 
+    /// # Remarks
+    /// The descriptions of the commands are passed to [`set_commands`] of `cli`,
+    /// so that their names are completed in interactive mode, matched as set 
+    /// using [`set_ignore_case`] and [`set_abbreviations`] and their keys are 
+    /// checked.
+    /// 
+    /// Unknown commands, keys and arguments, invalid values, repeated and
+    /// missing keys as well as errors in the input are reported to `out`. As 
+    /// with [`lightcli!`], a command is still completed after an unknown key or
+    /// argument has been reported, e.g. for `PWM 3 x=5`, while it is aborted if
+    /// a value is invalid, a key is repeated or missing or the input has an 
+    /// error. The prompt is printed once all of the data has been parsed.
+    /// 
+    /// [`set_commands`]: struct.LightCliInput.html#method.set_commands
+    /// [`set_ignore_case`]: struct.LightCliInput.html#method.set_ignore_case
+    /// [`set_abbreviations`]: struct.LightCliInput.html#method.set_abbreviations
+    /// [`lightcli!`]: macro.lightcli.html
+    pub fn dispatch<SLEN, QLEN, HLEN>(&mut self, cli: &mut LightCliInput<'a, SLEN, QLEN, HLEN>,
+        out: &mut LightCliOutput<E, OLEN>) -> nb::Result<(), tokenizer::Error>
+        where SLEN: ArrayLength<u8>, QLEN: ArrayLength<u8>,
+            HLEN: ArrayLength<::heapless::Vec<u8, SLEN>> {
+        let table = self.table;
+        let commands = &mut *self.commands;
+        let failed = &mut self.failed;
+
+        cli.set_commands(table);
+        let res = cli.parse_data(|event| handle(table, commands, out, failed, event));
+
+        let _ = out.show_prompt();
+        res
+    }
+}
+
+/// Find the command called `name` using its entry in `table`.
+fn find<'c, 'd, E, OLEN>(table: &[CommandInfo], commands: &'c mut [&'d mut (dyn Command<E, OLEN> + 'd)], name: &str)
+    -> Option<&'c mut (dyn Command<E, OLEN> + 'd)> where OLEN: ArrayLength<u8> {
+    let info = CommandInfo::find(table, name)?;
+    let index = table.iter().position(|entry| ptr::eq(entry, info))?;
+    commands.get_mut(index).map(|command| &mut **command)
+}
+
+/// Pass a single event on to its command or report it to `out`. `failed` is
+/// set once a key or argument of the line has been rejected.
+fn handle<E, OLEN>(table: &[CommandInfo], commands: &mut [&mut dyn Command<E, OLEN>], out: &mut LightCliOutput<E, OLEN>,
+    failed: &mut bool, event: CallbackCommand) where OLEN: ArrayLength<u8> {
+    let res = match event {
+        CallbackCommand::Attribute(cmd, key, val) => 
+            find(table, commands, cmd).map_or(Ok(()), |command| command.on_attribute(key, val)),
+        CallbackCommand::Argument(cmd, index, word) => 
+            find(table, commands, cmd).map_or(Ok(()), |command| command.on_argument(index, word)),
+        _ => {
+            // the line has ended, so the command is either completed or aborted
+            let cmd = match event {
+                CallbackCommand::Command(cmd) | CallbackCommand::TooManyArguments(cmd) | 
+                    CallbackCommand::RepeatedKey(cmd, _) | CallbackCommand::MissingKey(cmd, _) | 
+                    CallbackCommand::Ambiguous(cmd, _, _) => Some(cmd),
+                CallbackCommand::Error { cmd, .. } => cmd,
+                _ => None,
+            };
+
+            match (&event, cmd.and_then(|cmd| find(table, commands, cmd))) {
+                (&CallbackCommand::Command(_), Some(command)) if !*failed => command.on_complete(out),
+                (&CallbackCommand::Command(_), Some(command)) => command.on_abort(),
+                (_, command) => {
+                    if let Some(command) = command {
+                        command.on_abort();
+                    }
+                    let _ = write_error(out, &event);
+                },
+            }
+            *failed = false;
+            out.request_prompt();
+            return;
+        },
+    };
+
+    if let Err(err) = res {
+        // as with `lightcli!`, unknown keys and arguments are only reported
+        if err == KeyError::Invalid {
+            *failed = true;
+        }
+        let _ = write_key_error(out, &event, err);
+    }
+    out.request_prompt();
+}
+
+/// The message reporting an event, which is written when displayed. Created 
+/// using [`message`], [`key_message`] or [`subcommand_message`].
+/// 
+/// [`message`]: enum.CallbackCommand.html#method.message
+/// [`key_message`]: enum.CallbackCommand.html#method.key_message
+/// [`subcommand_message`]: enum.CallbackCommand.html#method.subcommand_message
+#[derive(Clone, Copy)]
+pub struct ErrorMessage<'a> {
+    event: CallbackCommand<'a>,
+    reason: Reason,
+}
+
+/// Why the key value pair or word of an event is reported.
+#[derive(Clone, Copy, PartialEq)]
+enum Reason {
+    Unknown,
+    Invalid,
+    /// The word is not a subcommand of its group.
+    Subcommand,
+}
+
+impl<'a> CallbackCommand<'a> {
+    /// The message reporting the event as an error, e.g. `Missing key for 
+    /// command PWM: duty`. A key value pair or word is reported as unknown and
+    /// a command event as an unknown command.
+    pub fn message(&self) -> ErrorMessage<'a> {
+        ErrorMessage { event: *self, reason: Reason::Unknown }
+    }
+
+    /// The message reporting the key value pair or word of the event as 
+    /// rejected for the reason `err`, e.g. `Invalid value for key Speed: fast`. 
+    /// Any other event is reported as by [`message`].
+    /// 
+    /// [`message`]: #method.message
+    pub fn key_message(&self, err: KeyError) -> ErrorMessage<'a> {
+        let reason = match err {
+            KeyError::Unknown => Reason::Unknown,
+            KeyError::Invalid => Reason::Invalid,
+        };
+        ErrorMessage { event: *self, reason }
+    }
+
+    /// The message reporting the word of an argument as an unknown subcommand
+    /// of its command, which is a group, e.g. `Unknown subcommand for command
+    /// NET: FOO`. Any other event is reported as by [`message`].
+    /// 
+    /// [`message`]: #method.message
+    pub fn subcommand_message(&self) -> ErrorMessage<'a> {
+        ErrorMessage { event: *self, reason: Reason::Subcommand }
+    }
+}
+
+impl<'a> fmt::Display for ErrorMessage<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.event {
+            CallbackCommand::Attribute(_, key, val) if self.reason == Reason::Invalid => 
+                write!(f, "Invalid value for key {}: {}", key, val),
+            CallbackCommand::Attribute(cmd, key, _) => write!(f, "Unknown key for command {}: {}", cmd, key),
+            CallbackCommand::Argument(_, index, word) if self.reason == Reason::Invalid => 
+                write!(f, "Invalid value for key [{}]: {}", index, word),
+            CallbackCommand::Argument(cmd, _, word) if self.reason == Reason::Subcommand => 
+                write!(f, "Unknown subcommand for command {}: {}", cmd, word),
+            CallbackCommand::Argument(cmd, _, word) => 
+                write!(f, "Unexpected argument for command {}: {}", cmd, word),
+            CallbackCommand::Command(cmd) => write!(f, "Unknown command: {}", cmd),
+            CallbackCommand::TooManyArguments(cmd) => write!(f, "Too many arguments for command: {}", cmd),
+            CallbackCommand::RepeatedKey(cmd, key) => write!(f, "Repeated key for command {}: {}", cmd, key),
+            CallbackCommand::MissingKey(cmd, key) => write!(f, "Missing key for command {}: {}", cmd, key),
+            CallbackCommand::Ambiguous(cmd, word, names) if names.is_key() => 
+                write!(f, "Ambiguous key for command {}: {} ({})", cmd, word, names),
+            CallbackCommand::Ambiguous("", word, names) => write!(f, "Ambiguous command: {} ({})", word, names),
+            CallbackCommand::Ambiguous(cmd, word, names) => 
+                write!(f, "Ambiguous subcommand for command {}: {} ({})", cmd, word, names),
+            CallbackCommand::Error { kind, position, .. } => write!(f, "{} at column {}", kind, position),
+        }
+    }
+}
+
+/// Write the error message for `event` to `out`, as described for [`message`].
+/// 
+/// [`message`]: enum.CallbackCommand.html#method.message
+pub(crate) fn write_error(out: &mut dyn Write, event: &CallbackCommand) -> fmt::Result {
+    writeln!(out, "{}", event.message())
+}
+
+/// Write the error message for the key value pair or word of `event` rejected
+/// for the reason `err`, as described for [`key_message`].
+/// 
+/// [`key_message`]: enum.CallbackCommand.html#method.key_message
+pub(crate) fn write_key_error(out: &mut dyn Write, event: &CallbackCommand, err: KeyError) -> fmt::Result {
+    writeln!(out, "{}", event.key_message(err))
+}
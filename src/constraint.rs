@@ -0,0 +1,106 @@
+use core::fmt;
+use heapless::{ArrayLength, String};
+use core::ops::{Range, RangeFrom, RangeInclusive, RangeTo, RangeToInclusive};
+
+/// A condition the value of a key has to fulfil, given after the key in
+/// [`lightcli!`] or [`lightcli_adv!`], e.g. `"Duty": u8 where 0..=100`.
+/// 
+/// Ranges restrict typed values, arrays such as `["fast", "slow"]` list the 
+/// allowed values and [`MaxLen`] limits the length of untyped and string values.
+/// 
+/// [`lightcli!`]: macro.lightcli.html
+/// [`lightcli_adv!`]: macro.lightcli_adv.html
+/// [`MaxLen`]: struct.MaxLen.html
+pub trait Constraint<T: ?Sized> {
+    /// Returns true if the value fulfils the constraint.
+    fn check(&self, value: &T) -> bool;
+
+    /// Describe why a value has been rejected, e.g. `out of range 0..100`.
+    fn describe(&self, f: &mut fmt::Formatter) -> fmt::Result;
+}
+
+/// Limits the number of characters of an untyped value or a value typed as
+/// a heapless `String`.
+#[derive(Clone, Copy, Debug)]
+pub struct MaxLen(pub usize);
+
+/// Describes the constraint a value has violated when displayed.
+pub struct Violation<'a, T: ?Sized + 'a>(pub &'a dyn Constraint<T>);
+
+impl<'a, T: ?Sized> fmt::Display for Violation<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.describe(f)
+    }
+}
+
+macro_rules! impl_constraint_range {
+    ($($range:ident),*) => {
+        $(
+        impl<T> Constraint<T> for $range<T> where T: PartialOrd + fmt::Debug {
+            fn check(&self, value: &T) -> bool {
+                self.contains(value)
+            }
+
+            fn describe(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "out of range {:?}", self)
+            }
+        }
+        )*
+    };
+}
+
+impl_constraint_range!(Range, RangeFrom, RangeInclusive, RangeTo, RangeToInclusive);
+
+impl<T, const N: usize> Constraint<T> for [T; N] where T: PartialEq + fmt::Debug {
+    fn check(&self, value: &T) -> bool {
+        self.contains(value)
+    }
+
+    fn describe(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "not one of ")?;
+        for (i, v) in self.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{:?}", v)?;
+        }
+        Ok(())
+    }
+}
+
+impl<const N: usize> Constraint<str> for [&str; N] {
+    fn check(&self, value: &str) -> bool {
+        self.contains(&value)
+    }
+
+    fn describe(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "not one of ")?;
+        for (i, v) in self.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}", v)?;
+        }
+        Ok(())
+    }
+}
+
+impl Constraint<str> for MaxLen {
+    fn check(&self, value: &str) -> bool {
+        value.chars().count() <= self.0
+    }
+
+    fn describe(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "longer than {} characters", self.0)
+    }
+}
+
+impl<N> Constraint<String<N>> for MaxLen where N: ArrayLength<u8> {
+    fn check(&self, value: &String<N>) -> bool {
+        Constraint::<str>::check(self, value)
+    }
+
+    fn describe(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        Constraint::<str>::describe(self, f)
+    }
+}
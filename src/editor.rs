@@ -0,0 +1,578 @@
+use core::str;
+
+use heapless::{ArrayLength, Vec};
+
+use matching::Matching;
+use registry::CommandInfo;
+
+const CTRL_A: u8 = 0x01;
+const CTRL_C: u8 = 0x03;
+const CTRL_E: u8 = 0x05;
+const BACKSPACE: u8 = 0x08;
+const TAB: u8 = 0x09;
+const CTRL_N: u8 = 0x0e;
+const CTRL_P: u8 = 0x10;
+const CTRL_U: u8 = 0x15;
+const CTRL_W: u8 = 0x17;
+const ESCAPE: u8 = 0x1b;
+const DELETE: u8 = 0x7f;
+
+/// The change to the current line caused by a received byte.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Edit {
+    /// The byte has been ignored.
+    None,
+    /// The byte has been appended to the line.
+    Insert(u8),
+    /// The given number of characters have been removed from the end of the line.
+    Erase(usize),
+    /// The cursor has been moved left by the given number of characters.
+    Left(usize),
+    /// The cursor has been moved right by the given number of characters.
+    Right(usize),
+    /// The line has been changed in front of the end. After moving the cursor
+    /// `left` characters to the left, the line has to be redrawn from the byte
+    /// `from` onwards.
+    Redraw { left: usize, from: usize },
+    /// The line consisting of the given number of characters has been replaced
+    /// with a different one.
+    Replace(usize),
+    /// The given number of bytes have been appended to the line to complete
+    /// a name.
+    Complete(usize),
+    /// The given number of bytes have been appended to the line, but there
+    /// are still multiple names it could be completed to.
+    Ambiguous(usize),
+    /// The line is complete.
+    Enter,
+    /// The line has been aborted.
+    Abort,
+}
+
+/// The progress through an ANSI escape sequence.
+#[derive(Clone, Copy, PartialEq)]
+enum Sequence {
+    None,
+    Escape,
+    /// Control sequence introduced by `ESC [`, with the first parameter and
+    /// whether it is still being read.
+    Csi(u8, bool),
+    /// Single character sequence introduced by `ESC O`.
+    Ss3,
+}
+
+/// Buffers the line that is currently being typed and applies editing keys
+/// before it is passed on to the tokenizer.
+pub struct LineEditor<'c, SLEN, HLEN> 
+where SLEN: ArrayLength<u8>, HLEN: ArrayLength<Vec<u8, SLEN>> {
+    line: Vec<u8, SLEN>,
+    /// The maximum number of bytes of a line in addition to the capacity.
+    max_len: usize,
+    /// The position in the line where characters are inserted.
+    cursor: usize,
+    complete: bool,
+    last_cr: bool,
+    utf8: bool,
+    sequence: Sequence,
+    history: Vec<Vec<u8, SLEN>, HLEN>,
+    /// The position of the oldest entry once the history is full.
+    oldest: usize,
+    /// The number of entries before the newest one currently shown.
+    recall: Option<usize>,
+    commands: &'c [CommandInfo],
+    matching: Matching,
+}
+
+impl<'c, SLEN, HLEN> LineEditor<'c, SLEN, HLEN> 
+where SLEN: ArrayLength<u8>, HLEN: ArrayLength<Vec<u8, SLEN>> {
+    pub fn new() -> Self {
+        Self {
+            line: Vec::new(),
+            max_len: usize::MAX,
+            cursor: 0,
+            complete: false,
+            last_cr: false,
+            utf8: true,
+            sequence: Sequence::None,
+            history: Vec::new(),
+            oldest: 0,
+            recall: None,
+            commands: &[],
+            matching: Matching::default(),
+        }
+    }
+
+    /// Set the commands used to complete names.
+    pub fn set_commands(&mut self, commands: &'c [CommandInfo]) {
+        self.commands = commands;
+    }
+
+    /// Set how the names of the line are matched, which should be the same 
+    /// as for the lexer.
+    pub fn set_matching(&mut self, matching: Matching) {
+        self.matching = matching;
+    }
+
+    /// Limit the length of a line to `max_len` bytes, e.g. so that it fits 
+    /// into the input buffer.
+    pub fn set_max_len(&mut self, max_len: usize) {
+        self.max_len = max_len;
+    }
+
+    /// Set whether characters may consist of multiple bytes.
+    pub fn set_utf8(&mut self, utf8: bool) {
+        self.utf8 = utf8;
+    }
+
+    /// Returns true if the line has been completed and should be passed on.
+    pub fn is_complete(&self) -> bool {
+        self.complete
+    }
+
+    pub fn line(&self) -> &[u8] {
+        &self.line
+    }
+
+    /// Start a new empty line.
+    pub fn clear(&mut self) {
+        // assign rather than `clear`, which trips a debug assertion in heapless
+        self.line = Vec::new();
+        self.cursor = 0;
+        self.complete = false;
+        self.recall = None;
+    }
+
+    /// The entries of the history, starting with the oldest one.
+    pub fn history<'a>(&'a self) -> impl Iterator<Item = &'a [u8]> + 'a {
+        let len = self.history.len();
+        (0..len).map(move |i| &self.history[(self.oldest + i) % len][..])
+    }
+
+    /// The number of characters after the cursor.
+    pub fn chars_after_cursor(&self) -> usize {
+        self.char_count(&self.line[self.cursor..])
+    }
+
+    fn char_count(&self, bytes: &[u8]) -> usize {
+        if self.utf8 {
+            bytes.iter().filter(|&&b| !is_continuation(b)).count()
+        } else {
+            bytes.len()
+        }
+    }
+
+    /// The start of the character before `pos`.
+    fn prev_char(&self, pos: usize) -> usize {
+        let mut pos = pos;
+        while pos > 0 {
+            pos -= 1;
+            // keep going until the first byte of a UTF-8 sequence
+            if !self.utf8 || !is_continuation(self.line[pos]) {
+                break;
+            }
+        }
+        pos
+    }
+
+    /// The end of the character starting at `pos`.
+    fn next_char(&self, pos: usize) -> usize {
+        let mut pos = pos;
+        if pos < self.line.len() {
+            pos += 1;
+            while pos < self.line.len() && self.utf8 && is_continuation(self.line[pos]) {
+                pos += 1;
+            }
+        }
+        pos
+    }
+
+    /// Remove the bytes from `start` to `end`.
+    fn remove(&mut self, start: usize, end: usize) {
+        self.line[start..].rotate_left(end - start);
+        for _ in start..end {
+            self.line.pop();
+        }
+    }
+
+    /// Append a byte to the line and return false if the line is full.
+    fn push(&mut self, b: u8) -> bool {
+        self.line.len() < self.max_len && self.line.push(b).is_ok()
+    }
+
+    /// Insert a byte at the cursor.
+    fn insert(&mut self, b: u8) -> Edit {
+        if !self.push(b) {
+            return Edit::None
+        }
+
+        let pos = self.cursor;
+        self.cursor += 1;
+
+        if self.cursor == self.line.len() {
+            return Edit::Insert(b)
+        }
+
+        self.line[pos..].rotate_right(1);
+
+        // only redraw once a character has been completed
+        let start = self.prev_char(self.cursor);
+        let len = if self.utf8 {
+            match self.line[start] {
+                0xc0..=0xdf => 2,
+                0xe0..=0xef => 3,
+                0xf0..=0xff => 4,
+                _ => 1,
+            }
+        } else {
+            1
+        };
+
+        if self.cursor - start < len {
+            return Edit::None
+        }
+        Edit::Redraw { left: 0, from: start }
+    }
+
+    /// Remove everything from `start` up to the cursor.
+    fn erase_before(&mut self, start: usize) -> Edit {
+        let end = self.cursor;
+        let count = self.char_count(&self.line[start..end]);
+        let at_end = end == self.line.len();
+
+        if count == 0 {
+            return Edit::None
+        }
+
+        self.remove(start, end);
+        self.cursor = start;
+
+        if at_end {
+            Edit::Erase(count)
+        } else {
+            Edit::Redraw { left: count, from: start }
+        }
+    }
+
+    /// Remove the word before the cursor and any spaces following it.
+    fn erase_word(&mut self) -> Edit {
+        let mut start = self.cursor;
+
+        while start > 0 && self.line[start - 1] == b' ' {
+            start -= 1;
+        }
+        while start > 0 && self.line[start - 1] != b' ' {
+            start -= 1;
+        }
+        self.erase_before(start)
+    }
+
+    /// Remove the character at the cursor.
+    fn delete(&mut self) -> Edit {
+        let end = self.next_char(self.cursor);
+
+        if end == self.cursor {
+            return Edit::None
+        }
+
+        self.remove(self.cursor, end);
+        Edit::Redraw { left: 0, from: self.cursor }
+    }
+
+    fn left(&mut self) -> Edit {
+        if self.cursor == 0 {
+            return Edit::None
+        }
+        self.cursor = self.prev_char(self.cursor);
+        Edit::Left(1)
+    }
+
+    fn right(&mut self) -> Edit {
+        if self.cursor == self.line.len() {
+            return Edit::None
+        }
+        self.cursor = self.next_char(self.cursor);
+        Edit::Right(1)
+    }
+
+    fn home(&mut self) -> Edit {
+        let count = self.char_count(&self.line[..self.cursor]);
+        self.cursor = 0;
+        if count > 0 { Edit::Left(count) } else { Edit::None }
+    }
+
+    fn end(&mut self) -> Edit {
+        let count = self.chars_after_cursor();
+        self.cursor = self.line.len();
+        if count > 0 { Edit::Right(count) } else { Edit::None }
+    }
+
+    /// Add the completed line to the history, replacing the oldest entry 
+    /// once the history is full. Empty lines and repetitions are skipped.
+    fn add_history(&mut self) {
+        let len = self.history.len();
+
+        if self.line.is_empty() || self.history.capacity() == 0 {
+            return
+        }
+
+        if len > 0 && self.history[(self.oldest + len - 1) % len] == self.line {
+            return
+        }
+
+        if len < self.history.capacity() {
+            // there is space left
+            let _ = self.history.push(self.line.clone());
+        } else {
+            self.history[self.oldest] = self.line.clone();
+            self.oldest = (self.oldest + 1) % len;
+        }
+    }
+
+    /// Replace the line with the history entry `recall` entries before the 
+    /// newest one, or an empty line if `recall` is `None`.
+    fn show_history(&mut self, recall: Option<usize>) -> Edit {
+        let count = self.char_count(&self.line[..self.cursor]);
+        let len = self.history.len();
+
+        self.recall = recall;
+        self.line = match recall {
+            Some(i) => self.history[(self.oldest + len - 1 - i) % len].clone(),
+            None => Vec::new(),
+        };
+        self.cursor = self.line.len();
+        Edit::Replace(count)
+    }
+
+    fn history_older(&mut self) -> Edit {
+        let recall = self.recall.map_or(0, |i| i + 1);
+
+        if recall < self.history.len() {
+            self.show_history(Some(recall))
+        } else {
+            Edit::None
+        }
+    }
+
+    fn history_newer(&mut self) -> Edit {
+        match self.recall {
+            None => Edit::None,
+            Some(0) => self.show_history(None),
+            Some(i) => self.show_history(Some(i - 1)),
+        }
+    }
+
+    /// The start of the last word of the line.
+    fn word_start(&self) -> usize {
+        self.line.iter().rposition(|&b| b == b' ').map_or(0, |i| i + 1)
+    }
+
+    /// The entry of `commands` named by `word`, as the lexer matches it.
+    fn find(&self, commands: &'c [CommandInfo], word: &[u8]) -> Option<&'c CommandInfo> {
+        let word = str::from_utf8(word).ok()?;
+        self.matching.find(commands, |info| info.name, word).ok()?
+    }
+
+    /// The command named by the words of the line before `end`, following its 
+    /// subcommands as the lexer does, and whether the next word may still name
+    /// one of its subcommands.
+    fn command_path(&self, end: usize) -> Option<(&'c CommandInfo, bool)> {
+        let mut words = self.line[..end].split(|&b| b == b' ').filter(|word| !word.is_empty());
+        let mut info = self.find(self.commands, words.next()?)?;
+        let mut open = true;
+
+        for word in words {
+            if word.contains(&b'=') {
+                open = false;
+            } else if let Some(sub) = self.find(info.subcommands, word).filter(|_| open) {
+                info = sub;
+            }
+        }
+        Some((info, open))
+    }
+
+    /// The commands the word starting at `start` may name.
+    fn command_names(&self, start: usize) -> &'c [CommandInfo] {
+        if start == 0 {
+            return self.commands
+        }
+        match self.command_path(start) {
+            Some((info, true)) => info.subcommands,
+            _ => &[],
+        }
+    }
+
+    /// Call `f` with every name the last word of the line can be completed to,
+    /// returning the length of that word.
+    /// 
+    /// # Remarks
+    /// The first word is completed to command names, any other word to the
+    /// keys and subcommands of the command. Values are not completed. Names 
+    /// are matched as set using [`set_matching`], e.g. ignoring case, but 
+    /// completed as spelled in the table.
+    /// 
+    /// [`set_matching`]: #method.set_matching
+    pub fn completions<F>(&self, f: F) -> usize where F: FnMut(&'static str) {
+        let start = self.word_start();
+        let word = match str::from_utf8(&self.line[start..]) {
+            Ok(word) if !word.contains('=') => word,
+            _ => return self.line.len() - start,
+        };
+
+        let keys = match start {
+            0 => &[],
+            _ => self.command_path(start).map_or(&[][..], |(info, _)| info.keys),
+        };
+
+        self.command_names(start).iter().map(|info| info.name)
+            .chain(keys.iter().map(|key| key.name))
+            .filter(|name| self.matching.starts_with(name, word))
+            .for_each(f);
+
+        word.len()
+    }
+
+    /// Complete the last word of the line as far as possible. This only works
+    /// if the cursor is at the end of the line.
+    fn complete(&mut self) -> Edit {
+        if self.cursor != self.line.len() {
+            return Edit::None
+        }
+
+        let commands = self.command_names(self.word_start());
+        let mut count = 0;
+        let mut common : &'static [u8] = &[];
+
+        let len = self.completions(|name| {
+            let name = name.as_bytes();
+            let same = common.iter().zip(name).take_while(|(a, b)| a == b).count();
+            common = if count == 0 { name } else { &common[..same] };
+            count += 1;
+        });
+
+        if count == 0 {
+            return Edit::None
+        }
+
+        let mut added = 0;
+        for &b in &common[len..] {
+            if !self.push(b) {
+                break;
+            }
+            added += 1;
+        }
+
+        let command = commands.iter().any(|info| info.name.as_bytes() == common);
+        if count == 1 && command && self.push(b' ') {
+            // start the next word after a complete command or subcommand name
+            added += 1;
+        }
+        self.cursor = self.line.len();
+
+        if count > 1 {
+            Edit::Ambiguous(added)
+        } else {
+            Edit::Complete(added)
+        }
+    }
+
+    /// Handle the final byte of an escape sequence with its first parameter.
+    fn escape_sequence(&mut self, b: u8, param: u8) -> Edit {
+        match b {
+            b'A' => self.history_older(),
+            b'B' => self.history_newer(),
+            b'C' => self.right(),
+            b'D' => self.left(),
+            b'H' => self.home(),
+            b'F' => self.end(),
+            b'~' => match param {
+                1 | 7 => self.home(),
+                3 => self.delete(),
+                4 | 8 => self.end(),
+                _ => Edit::None,
+            },
+            _ => Edit::None,
+        }
+    }
+
+    /// Apply a received byte to the line.
+    /// 
+    /// # Remarks
+    /// The cursor is moved using the left and right arrow keys, Home and End or
+    /// Ctrl-A and Ctrl-E. Backspace removes the character before the cursor and
+    /// the delete key the one at the cursor. Ctrl-U removes everything before 
+    /// the cursor and Ctrl-W the word before it. Ctrl-C aborts the line. The up
+    /// and down arrow keys, as well as Ctrl-P and Ctrl-N, move through the 
+    /// history and tab completes command names and keys. Any other control characters or 
+    /// escape sequences are ignored, as are characters which do not fit into 
+    /// the line.
+    pub fn feed(&mut self, b: u8) -> Edit {
+        let last_cr = self.last_cr;
+        self.last_cr = b == b'\r';
+
+        match self.sequence {
+            Sequence::None => (),
+            Sequence::Escape => {
+                self.sequence = match b {
+                    b'[' => Sequence::Csi(0, true),
+                    b'O' => Sequence::Ss3,
+                    _ => Sequence::None,
+                };
+                return Edit::None
+            },
+            Sequence::Csi(param, first) => {
+                match b {
+                    b'0'..=b'9' if first => {
+                        let param = param.saturating_mul(10).saturating_add(b - b'0');
+                        self.sequence = Sequence::Csi(param, true);
+                    },
+                    0x40..=0x7e => {
+                        self.sequence = Sequence::None;
+                        return self.escape_sequence(b, param)
+                    },
+                    // skip any further parameter and intermediate bytes
+                    _ => self.sequence = Sequence::Csi(param, false),
+                }
+                return Edit::None
+            },
+            Sequence::Ss3 => {
+                self.sequence = Sequence::None;
+                return self.escape_sequence(b, 0)
+            },
+        }
+
+        match b {
+            // the newline of a `\r\n` sequence has already been handled
+            b'\n' if last_cr => Edit::None,
+            b'\r' | b'\n' => {
+                self.add_history();
+                self.complete = true;
+                Edit::Enter
+            },
+            BACKSPACE | DELETE => {
+                let start = self.prev_char(self.cursor);
+                self.erase_before(start)
+            },
+            CTRL_U => self.erase_before(0),
+            CTRL_W => self.erase_word(),
+            CTRL_A => self.home(),
+            CTRL_E => self.end(),
+            CTRL_C => {
+                self.clear();
+                Edit::Abort
+            },
+            TAB => self.complete(),
+            CTRL_P => self.history_older(),
+            CTRL_N => self.history_newer(),
+            ESCAPE => {
+                self.sequence = Sequence::Escape;
+                Edit::None
+            },
+            b if b < 0x20 => Edit::None,
+            b => self.insert(b),
+        }
+    }
+}
+
+fn is_continuation(b: u8) -> bool {
+    b >> 6 == 0b10
+}
@@ -1,25 +1,265 @@
+use core::fmt::Write;
+
 use heapless;
+use heapless::consts::*;
 use nb;
 use tokenizer;
 
-use tokenizer::{Tokenizer};
+use tokenizer::{Encoding, FillError, OverrunPolicy, Tokenizer};
 use lexer::{Lexer, CallbackCommand};
+use editor::{Edit, LineEditor};
+use matching::Matching;
+use registry::{CommandInfo, HelpTopic, SeenKeys};
+use parsed::{ParsedArg, ParsedCommand};
+use output::LightCliOutput;
 use hal::serial::Read;
 
-pub struct LightCliInput<SLEN> where SLEN: heapless::ArrayLength<u8> {
-    tokenizer: Tokenizer<SLEN>,
-    lexer: Lexer<SLEN>
+/// The command line input, which buffers and parses the received data.
+/// 
+/// # Type Parameters
+/// * `'c` - The lifetime of the table of commands passed to [`set_commands`].
+/// * `SLEN` - The maximum length of a command, key or value.
+/// * `QLEN` - The number of bytes that can be buffered between calls to 
+///   [`parse_data`], 64 by default.
+/// * `HLEN` - The number of lines kept in the history in interactive mode, 
+///   none by default. Every line takes up `SLEN` bytes.
+/// 
+/// [`parse_data`]: struct.LightCliInput.html#method.parse_data
+/// [`set_commands`]: struct.LightCliInput.html#method.set_commands
+pub struct LightCliInput<'c, SLEN, QLEN = U64, HLEN = U0> 
+where SLEN: heapless::ArrayLength<u8>, QLEN: heapless::ArrayLength<u8>, 
+    HLEN: heapless::ArrayLength<heapless::Vec<u8, SLEN>> {
+    tokenizer: Tokenizer<SLEN, QLEN>,
+    lexer: Lexer<'c, SLEN>,
+    editor: LineEditor<'c, SLEN, HLEN>,
+    commands: &'c [CommandInfo],
+    seen: SeenKeys<'c>,
+    /// Whether a key value pair or word of the current line has been rejected.
+    rejected: bool,
+    help: HelpTopic<'c>,
+    interactive: bool,
+    echo: bool,
+}
+
+impl<'c, SLEN, QLEN, HLEN> Default for LightCliInput<'c, SLEN, QLEN, HLEN>
+where SLEN: heapless::ArrayLength<u8>, QLEN: heapless::ArrayLength<u8>, 
+    HLEN: heapless::ArrayLength<heapless::Vec<u8, SLEN>> {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
-impl<SLEN : heapless::ArrayLength<u8>> LightCliInput<SLEN> {
+impl<'c, SLEN, QLEN, HLEN> LightCliInput<'c, SLEN, QLEN, HLEN>
+where SLEN: heapless::ArrayLength<u8>, QLEN: heapless::ArrayLength<u8>, 
+    HLEN: heapless::ArrayLength<heapless::Vec<u8, SLEN>> {
     /// Create a new LightCLI instance.
     pub fn new() -> Self {
+        let tokenizer = Tokenizer::new();
+        let mut editor = LineEditor::new();
+        // a completed line has to fit into the buffer together with its newline
+        editor.set_max_len(tokenizer.capacity() - 1);
+
         Self {
-            tokenizer: Tokenizer::new(),
+            tokenizer,
             lexer: Lexer::new(),
+            editor,
+            commands: &[],
+            seen: SeenKeys::new(),
+            rejected: false,
+            help: HelpTopic::default(),
+            interactive: false,
+            echo: false,
         }
     }
 
+    /// Enable or disable interactive line editing, which is disabled by default.
+    /// 
+    /// # Arguments
+    /// * `interactive` - Whether the input is typed by a human at a terminal.
+    /// 
+    /// # Remarks
+    /// In interactive mode each line is kept back by [`fill`] until it is complete,
+    /// so that it can still be edited. Backspace and delete remove the last 
+    /// character, Ctrl-W removes the last word, Ctrl-U the whole line and 
+    /// Ctrl-C aborts the line. The cursor can be moved using the arrow keys, 
+    /// Home and End or Ctrl-A and Ctrl-E, in which case the editing keys act on
+    /// the text before the cursor. Lines can be at most `SLEN` bytes long and
+    /// have to fit into the buffer together with their line terminator, so 
+    /// no more than `QLEN - 1` bytes are accepted. Empty lines are skipped.
+    /// 
+    /// Completed lines are added to the history, if it has space for any lines.
+    /// The up and down arrow keys or Ctrl-P and Ctrl-N recall previous lines.
+    /// 
+    /// [`fill`]: struct.LightCliInput.html#method.fill
+    pub fn set_interactive(&mut self, interactive: bool) {
+        self.interactive = interactive;
+        self.editor.clear();
+    }
+
+    /// Enable or disable echoing the input back to the terminal, which is 
+    /// disabled by default.
+    /// 
+    /// # Arguments
+    /// * `echo` - Whether [`fill_echo`] should echo the input.
+    /// 
+    /// # Remarks
+    /// Echo is only performed in interactive mode, see [`set_interactive`]. Links
+    /// between two machines should leave it disabled.
+    /// 
+    /// [`fill_echo`]: struct.LightCliInput.html#method.fill_echo
+    /// [`set_interactive`]: struct.LightCliInput.html#method.set_interactive
+    pub fn set_echo(&mut self, echo: bool) {
+        self.echo = echo;
+    }
+
+    /// Enable or disable matching command names and keys regardless of their
+    /// case, which is disabled by default.
+    /// 
+    /// # Arguments
+    /// * `ignore_case` - Whether `hello name=foo` should match `HELLO Name=foo`.
+    /// 
+    /// # Remarks
+    /// Only the commands and keys passed to [`set_commands`] are matched, using
+    /// ASCII case folding. They are passed on to the callback of [`parse_data`]
+    /// as spelled in the table, so that [`lightcli!`] and [`lightcli_adv!`] find
+    /// them without any changes. Unknown commands and keys as well as all values 
+    /// are passed on as typed. Completion and the automatic `HELP` command match
+    /// names in the same way.
+    /// 
+    /// [`set_commands`]: struct.LightCliInput.html#method.set_commands
+    /// [`parse_data`]: struct.LightCliInput.html#method.parse_data
+    /// [`lightcli!`]: macro.lightcli.html
+    /// [`lightcli_adv!`]: macro.lightcli_adv.html
+    pub fn set_ignore_case(&mut self, ignore_case: bool) {
+        self.lexer.set_ignore_case(ignore_case);
+        self.editor.set_matching(self.lexer.matching());
+    }
+
+    /// Enable or disable abbreviating command names and keys, which is 
+    /// disabled by default.
+    /// 
+    /// # Arguments
+    /// * `abbreviations` - Whether `HEL N=Foo` should match `HELLO Name=Foo`.
+    /// 
+    /// # Remarks
+    /// A command, subcommand or key of the commands passed to [`set_commands`] 
+    /// may be given by the beginning of its name, as long as no other name 
+    /// begins the same way. A name given in full is always accepted, even if it
+    /// begins another name. Words without a value are not abbreviated to flags,
+    /// since they may be positional arguments.
+    /// 
+    /// As with [`set_ignore_case`], which may be combined with abbreviations, the
+    /// names are passed on as spelled in the table. An abbreviation of several 
+    /// names ends the line with an ambiguous event, which lists the names.
+    /// 
+    /// [`set_commands`]: struct.LightCliInput.html#method.set_commands
+    /// [`set_ignore_case`]: struct.LightCliInput.html#method.set_ignore_case
+    pub fn set_abbreviations(&mut self, abbreviations: bool) {
+        self.lexer.set_abbreviations(abbreviations);
+        self.editor.set_matching(self.lexer.matching());
+    }
+
+    /// How names are matched against the table of commands.
+    pub(crate) fn matching(&self) -> Matching {
+        self.lexer.matching()
+    }
+
+    /// Set the commands whose names are completed to when pressing tab in 
+    /// interactive mode and whose keys are checked by [`parse_data`]. This is 
+    /// done by the [`lightcli!`] and [`lightcli_adv!`] macros before parsing any data.
+    /// 
+    /// # Arguments
+    /// * `commands` - The table of all known commands.
+    /// 
+    /// # Remarks
+    /// If the first word is being typed, it is completed to a command name and 
+    /// otherwise to one of the keys or subcommands of the command. If there are multiple
+    /// names, the word is completed as far as possible and [`fill_echo`] lists 
+    /// all of them.
+    /// 
+    /// [`parse_data`]: struct.LightCliInput.html#method.parse_data
+    /// [`lightcli!`]: macro.lightcli.html
+    /// [`lightcli_adv!`]: macro.lightcli_adv.html
+    /// [`fill_echo`]: struct.LightCliInput.html#method.fill_echo
+    pub fn set_commands(&mut self, commands: &'c [CommandInfo]) {
+        self.commands = commands;
+        self.editor.set_commands(commands);
+    }
+
+    /// The command `HELP` has been asked to describe on the current line, 
+    /// which is used by [`lightcli!`].
+    /// 
+    /// [`lightcli!`]: macro.lightcli.html
+    #[doc(hidden)]
+    pub fn help_topic(&self) -> HelpTopic<'c> {
+        HelpTopic::new(self.help, self.lexer.matching())
+    }
+
+    /// Keep the command `HELP` has been asked to describe until the next call.
+    #[doc(hidden)]
+    pub fn set_help_topic(&mut self, topic: HelpTopic<'c>) {
+        self.help = topic;
+    }
+
+    /// The lines entered in interactive mode, starting with the oldest one. 
+    /// 
+    /// # Remarks
+    /// Only the last `HLEN` lines are kept and repeated lines are only stored
+    /// once. The lines are not guaranteed to be valid UTF-8, so use e.g. 
+    /// `core::str::from_utf8` before printing them.
+    pub fn history<'a>(&'a self) -> impl Iterator<Item = &'a [u8]> + 'a {
+        self.editor.history()
+    }
+
+    /// Set how received bytes are decoded into characters. By default the 
+    /// input is decoded as UTF-8 and invalid lines are reported as errors.
+    /// 
+    /// # Arguments
+    /// * `encoding` - The encoding to use for all further input.
+    pub fn set_encoding(&mut self, encoding: Encoding) {
+        self.tokenizer.set_encoding(encoding);
+        self.editor.set_utf8(encoding != Encoding::Latin1);
+    }
+
+    /// Set what happens when data is received while the buffer is full. By
+    /// default [`fill`] drops the byte which does not fit, stops reading and 
+    /// returns `FillError::BufferFull`.
+    /// 
+    /// # Arguments
+    /// * `policy` - The overrun policy to use for all further calls to [`fill`].
+    /// 
+    /// [`fill`]: struct.LightCliInput.html#method.fill
+    pub fn set_overrun_policy(&mut self, policy: OverrunPolicy) {
+        self.tokenizer.set_overrun_policy(policy)
+    }
+
+    /// The number of bytes dropped by [`fill`] because the buffer was full.
+    /// 
+    /// [`fill`]: struct.LightCliInput.html#method.fill
+    pub fn overruns(&self) -> usize {
+        self.tokenizer.overruns()
+    }
+
+    /// Reset the number of dropped bytes returned by [`overruns`] to zero.
+    /// 
+    /// [`overruns`]: struct.LightCliInput.html#method.overruns
+    pub fn reset_overruns(&mut self) {
+        self.tokenizer.reset_overruns()
+    }
+
+    /// The number of lines skipped because of an error in the input, such as 
+    /// a value which is too long or invalid UTF-8.
+    pub fn errors(&self) -> usize {
+        self.lexer.errors()
+    }
+
+    /// Reset the number of errors returned by [`errors`] to zero.
+    /// 
+    /// [`errors`]: struct.LightCliInput.html#method.errors
+    pub fn reset_errors(&mut self) {
+        self.lexer.reset_errors()
+    }
+
     /// Try to parse as much data from the internal ring buffer as possible.
     /// 
     /// # Arguments
@@ -30,9 +270,104 @@ impl<SLEN : heapless::ArrayLength<u8>> LightCliInput<SLEN> {
     /// pair the callback will be triggered with the current command string, current
     /// key and the corresponding value. When a newline is read the callback is 
     /// triggered with a command event.
-    pub fn parse_data<CB>(&mut self, callback: CB) -> nb::Result<(), tokenizer::Error> 
-        where CB: FnMut(CallbackCommand) -> () {
-        self.lexer.parse_data(&mut self.tokenizer, callback)
+    /// 
+    /// Words which are not followed by `=`, such as `3` in "LED 3 state=on", are 
+    /// positional arguments and trigger the callback with an argument event 
+    /// containing the index of the argument.
+    /// 
+    /// If a command, key or value does not fit into the string buffer, the rest
+    /// of the line is dropped and the callback is triggered with an error event
+    /// containing the column of the error instead of the command event. The 
+    /// same happens for any other error, such as invalid UTF-8 input, which is
+    /// also returned.
+    /// 
+    /// Keys and positional arguments of the commands passed to [`set_commands`]
+    /// may only be given once per line. A repeated key is skipped and triggers a 
+    /// repeated key event at the end of the line, as does a required key which 
+    /// is not given with a missing key event. In both cases the command event 
+    /// of the line is not triggered.
+    /// 
+    /// A word naming a subcommand of the current command, such as `IP` in 
+    /// "NET IP SET addr=10.0.0.1", is appended to the command, so that the events
+    /// of the line are triggered for the command `NET IP SET`. Positional 
+    /// arguments of a subcommand are counted from zero again. Subcommands are 
+    /// only recognised before the first key value pair of the line.
+    /// 
+    /// [`set_commands`]: struct.LightCliInput.html#method.set_commands
+    pub fn parse_data<CB>(&mut self, mut callback: CB) -> nb::Result<(), tokenizer::Error> 
+        where CB: FnMut(CallbackCommand) {
+        self.parse_data_checked(|event| {
+            callback(event);
+            true
+        })
+    }
+
+    /// Try to parse as much data from the internal ring buffer as possible, 
+    /// allowing the callback to reject key value pairs and words.
+    /// 
+    /// # Arguments
+    /// * `callback` - This is the callback that will receive all parsing events.
+    ///   It returns false if it rejects an attribute or argument event.
+    /// 
+    /// # Remarks
+    /// This behaves like [`parse_data`], except that the command event is not
+    /// triggered for a line on which an attribute or argument event has been 
+    /// rejected, e.g. because its value is invalid. The callback is expected to
+    /// report the reason. The return value is ignored for all other events.
+    /// 
+    /// [`parse_data`]: struct.LightCliInput.html#method.parse_data
+    pub fn parse_data_checked<CB>(&mut self, mut callback: CB) -> nb::Result<(), tokenizer::Error> 
+        where CB: FnMut(CallbackCommand) -> bool {
+        let commands = self.commands;
+        let seen = &mut self.seen;
+        let rejected = &mut self.rejected;
+
+        self.lexer.parse_data(&mut self.tokenizer, commands, |event| seen.check(commands, event, &mut |event| {
+            match event {
+                CallbackCommand::Attribute(..) | CallbackCommand::Argument(..) => {
+                    if !callback(event) {
+                        *rejected = true;
+                    }
+                },
+                CallbackCommand::Command(_) if *rejected => *rejected = false,
+                event => {
+                    // every other event ends the line
+                    *rejected = false;
+                    callback(event);
+                },
+            }
+        }))
+    }
+
+    /// Parse the buffered data and trigger the callback once for every complete line.
+    /// 
+    /// # Arguments
+    /// * `command` - The command the arguments of the current line are collected in.
+    /// * `callback` - The function to call with the complete command or an error.
+    /// 
+    /// # Remarks
+    /// Unlike [`parse_data`], the key value pairs and words of a line are collected
+    /// in `command`, which has to be kept between calls, and the callback is only
+    /// triggered with the whole command at the end of the line. This allows a 
+    /// command to be applied at once, or not at all if the line contains an error.
+    /// 
+    /// Errors are passed to the callback as the events of [`parse_data`]. A line 
+    /// with more arguments than `command` can hold is reported with a too many
+    /// arguments event. 
+    /// Repeated and missing keys are reported for the commands passed to 
+    /// [`set_commands`] and the command is not passed on in either case.
+    /// 
+    /// [`parse_data`]: struct.LightCliInput.html#method.parse_data
+    /// [`set_commands`]: struct.LightCliInput.html#method.set_commands
+    pub fn parse_commands<ALEN, CB>(&mut self, command: &mut ParsedCommand<SLEN, ALEN>, mut callback: CB) 
+        -> nb::Result<(), tokenizer::Error> 
+        where ALEN: heapless::ArrayLength<ParsedArg<SLEN>>, 
+            CB: FnMut(Result<&ParsedCommand<SLEN, ALEN>, CallbackCommand>) {
+        let commands = self.commands;
+        let seen = &mut self.seen;
+        self.lexer.parse_data(&mut self.tokenizer, commands, |event| seen.check(commands, event, &mut |event| {
+            command.collect(event, &mut callback)
+        }))
     }
 
     /// Copy as many available bytes from `ser` into the buffer as possible.
@@ -43,8 +378,126 @@ impl<SLEN : heapless::ArrayLength<u8>> LightCliInput<SLEN> {
     /// # Remarks
     /// 
     /// This will continue to try to read a byte from the serial device until the
-    /// device returns `nb::Error::WouldBlock`.
-    pub fn fill<E>(&mut self, ser: &mut Read<u8, Error=E>) -> nb::Result<(), E> {
-        self.tokenizer.fill(ser)
+    /// device returns `nb::Error::WouldBlock`. If a byte is received while the 
+    /// buffer is full, the overrun policy decides which bytes are dropped and 
+    /// whether reading stops with `FillError::BufferFull`. A full buffer is not
+    /// an error as long as no byte has been dropped. In interactive mode reading
+    /// always stops if a completed line does not fit into the buffer.
+    pub fn fill<E>(&mut self, ser: &mut dyn Read<u8, Error=E>) -> nb::Result<(), FillError<E>> {
+        if !self.interactive {
+            return self.tokenizer.fill(ser)
+        }
+
+        self.fill_interactive(ser, |_, _| ())
+    }
+
+    /// Copy as many available bytes from `ser` into the buffer as possible and 
+    /// echo them to `cl_out` if echo is enabled.
+    /// 
+    /// # Arguments
+    /// * `ser` - The serial interface to read from.
+    /// * `cl_out` - The output to echo the input to.
+    /// 
+    /// # Remarks
+    /// 
+    /// This behaves like [`fill`], but every accepted character is written to 
+    /// `cl_out`. Removed characters are erased using `\b \b` and completed lines 
+    /// are terminated with `\r\n`. When moving the cursor, editing in the middle
+    /// of the line or recalling a line from the history, the line is redrawn 
+    /// using ANSI escape sequences. If tab completion finds
+    /// multiple names, they are listed on a separate line before the prompt and
+    /// the line are printed again. The prompt is also printed after empty or 
+    /// aborted lines. Errors writing the echo are ignored.
+    /// 
+    /// [`fill`]: struct.LightCliInput.html#method.fill
+    pub fn fill_echo<E, EO, OLEN>(&mut self, ser: &mut dyn Read<u8, Error=E>, 
+        cl_out: &mut LightCliOutput<EO, OLEN>) -> nb::Result<(), FillError<E>> 
+        where OLEN: heapless::ArrayLength<u8> {
+        if !self.interactive {
+            return self.tokenizer.fill(ser)
+        }
+
+        if !self.echo {
+            return self.fill_interactive(ser, |_, _| ())
+        }
+
+        self.fill_interactive(ser, |edit, editor| {
+            let line = editor.line();
+            let _ = match edit {
+                Edit::None => Ok(()),
+                Edit::Insert(b) => cl_out.write_bytes(&[b]),
+                Edit::Erase(n) => (0..n).try_for_each(|_| cl_out.write_bytes(b"\x08 \x08")),
+                Edit::Left(n) => write!(cl_out, "\x1b[{}D", n),
+                Edit::Right(n) => write!(cl_out, "\x1b[{}C", n),
+                Edit::Redraw { left, from } => {
+                    if left > 0 {
+                        let _ = write!(cl_out, "\x1b[{}D", left);
+                    }
+                    let _ = cl_out.write_bytes(&line[from..]).and_then(|_| cl_out.write_bytes(b"\x1b[K"));
+
+                    // move back to the cursor
+                    match editor.chars_after_cursor() {
+                        0 => Ok(()),
+                        n => write!(cl_out, "\x1b[{}D", n),
+                    }
+                },
+                Edit::Replace(n) => {
+                    // move back to the start of the line and clear it
+                    if n > 0 {
+                        let _ = write!(cl_out, "\x1b[{}D", n);
+                    }
+                    cl_out.write_bytes(b"\x1b[K").and_then(|_| cl_out.write_bytes(line))
+                },
+                Edit::Complete(n) => cl_out.write_bytes(&line[line.len() - n..]),
+                Edit::Ambiguous(_) => {
+                    let mut separator : &[u8] = b"\r\n";
+                    editor.completions(|name| {
+                        let _ = cl_out.write_bytes(separator);
+                        let _ = cl_out.write_bytes(name.as_bytes());
+                        separator = b"  ";
+                    });
+                    let _ = cl_out.write_bytes(b"\r\n");
+                    cl_out.request_prompt();
+                    cl_out.show_prompt().and_then(|_| cl_out.write_bytes(line))
+                },
+                Edit::Enter => {
+                    let _ = cl_out.write_bytes(b"\r\n");
+                    // nothing is passed on for an empty line, so prompt right away
+                    if line.is_empty() {
+                        cl_out.request_prompt();
+                    }
+                    cl_out.show_prompt()
+                },
+                Edit::Abort => {
+                    let _ = cl_out.write_bytes(b"^C\r\n");
+                    cl_out.request_prompt();
+                    cl_out.show_prompt()
+                },
+            };
+        })
+    }
+
+    fn fill_interactive<E, CB>(&mut self, ser: &mut dyn Read<u8, Error=E>, mut callback: CB) -> nb::Result<(), FillError<E>> 
+        where CB: FnMut(Edit, &LineEditor<'c, SLEN, HLEN>) {
+        loop {
+            if self.editor.is_complete() {
+                let line = self.editor.line();
+
+                // empty lines are skipped
+                if !line.is_empty() && !self.tokenizer.enqueue_line(line) {
+                    return Err(nb::Error::Other(FillError::BufferFull))
+                }
+                self.editor.clear();
+            }
+
+            match ser.read() {
+                Err(nb::Error::WouldBlock) => return Ok(()),
+                Err(nb::Error::Other(e)) => return Err(nb::Error::Other(FillError::Serial(e))),
+                Ok(b) => {
+                    let edit = self.editor.feed(b);
+                    callback(edit, &self.editor)
+                },
+            }
+        }
     }
 }
\ No newline at end of file
@@ -1,6 +1,8 @@
 use nb;
 use heapless::{ArrayLength, String};
 
+use matching::{Candidates, Matching};
+use registry::CommandInfo;
 use tokenizer;
 use tokenizer::{Token, Tokenizer};
 
@@ -10,84 +12,340 @@ enum MachineState {
     NewCommand,
     NewCommandCR,
     Key,
+    Word,
     Value,
+    Aborted,
 }
 
+#[derive(Clone, Copy)]
 pub enum CallbackCommand<'a> {
+    /// A key value pair, e.g. `state=on`. Contains the command, the key and 
+    /// the value. The command of a subcommand is its whole path separated by 
+    /// spaces, e.g. `NET IP SET`.
     Attribute(&'a str, &'a str, &'a str),
+    /// A word without a value, e.g. `3` in `LED 3 state=on` or the flag `force`
+    /// in `RESET force`. Contains the command, the index of the argument 
+    /// counting from zero and the word. Flags which are keys of the command 
+    /// in the table passed to `set_commands` are not counted, so a flag has 
+    /// the index of the next positional argument.
+    Argument(&'a str, usize, &'a str),
     Command(&'a str),
+    /// The line has more key value pairs and words than the [`ParsedCommand`]
+    /// they are collected in can hold. Contains the command. It is only sent
+    /// by [`parse_commands`], instead of the command.
+    /// 
+    /// [`ParsedCommand`]: struct.ParsedCommand.html
+    /// [`parse_commands`]: struct.LightCliInput.html#method.parse_commands
+    TooManyArguments(&'a str),
+    /// A key or positional argument has been given twice on the same line. 
+    /// Contains the command and the first repeated key. It is sent at the end
+    /// of the line instead of the command event.
+    RepeatedKey(&'a str, &'a str),
+    /// A required key or positional argument has not been given. Contains the 
+    /// command and the key, e.g. `[0]` for a positional argument. The command
+    /// event of the line is not sent.
+    MissingKey(&'a str, &'a str),
+    /// A command, subcommand or key has been abbreviated to the beginning of
+    /// several names. Contains the command, which is empty if the command 
+    /// itself is abbreviated, the abbreviation and the names it may stand for.
+    /// It ends the line instead of the command event.
+    Ambiguous(&'a str, &'a str, Candidates<'a>),
+    /// The rest of the line has been skipped because of an error in the input,
+    /// such as a value or subcommand path which is too long or invalid UTF-8.
+    /// It ends the line instead of the command event.
+    Error {
+        /// The command, if it was read before the error occurred.
+        cmd: Option<&'a str>,
+        /// The kind of error.
+        kind: tokenizer::Error,
+        /// The column of the line at which the error occurred, counted in
+        /// characters from one.
+        position: usize,
+    },
 }
 
-pub struct Lexer<SLEN> where SLEN: ArrayLength<u8> {
+pub struct Lexer<'c, SLEN> where SLEN: ArrayLength<u8> {
     current_cmd: String<SLEN>,
     current_key: String<SLEN>,
+    /// The column at which the pending word starts.
+    key_column: usize,
+    arg_index: usize,
+    /// The entry of the current command in the table, if it is known.
+    command: Option<&'c CommandInfo>,
+    /// The subcommands the next word may name, which is empty once a key 
+    /// value pair has been given.
+    subcommands: &'c [CommandInfo],
+    matching: Matching,
     state: MachineState,
+    /// The number of error events sent.
+    errors: usize,
 }
 
-impl<SLEN> Lexer<SLEN> where SLEN: ArrayLength<u8> {
+impl<'c, SLEN> Lexer<'c, SLEN> where SLEN: ArrayLength<u8> {
     pub fn new() -> Self {
         Self {
             current_cmd: String::new(),
             current_key: String::new(),
+            key_column: 0,
+            arg_index: 0,
+            command: None,
+            subcommands: &[],
+            matching: Matching::default(),
             state: MachineState::NewCommand,
+            errors: 0,
         }
     }
 
-    pub fn parse_data<CB>(&mut self, tokenizer: &mut Tokenizer<SLEN>, mut callback: CB) -> nb::Result<(), tokenizer::Error> 
-        where CB: FnMut(CallbackCommand) -> () {
-        tokenizer.get_tokens(|token| {
+    /// The number of lines skipped because of an error in the input.
+    pub fn errors(&self) -> usize {
+        self.errors
+    }
+
+    pub fn reset_errors(&mut self) {
+        self.errors = 0;
+    }
+
+    /// Match the names of commands and keys ignoring ASCII case, in which case
+    /// they are passed on as spelled in the table.
+    pub fn set_ignore_case(&mut self, ignore_case: bool) {
+        self.matching.ignore_case = ignore_case;
+    }
+
+    /// Accept unique prefixes of the names of commands and keys, which are 
+    /// passed on as spelled in the table.
+    pub fn set_abbreviations(&mut self, abbreviations: bool) {
+        self.matching.abbreviations = abbreviations;
+    }
+
+    /// How names are matched against the table of commands.
+    pub fn matching(&self) -> Matching {
+        self.matching
+    }
+
+    /// The spelling of the key `key` in the table, or `key` itself if it is 
+    /// not a key of the current command. Fails if `key` abbreviates several keys.
+    fn key_name<'k>(&self, key: &'k str, matching: Matching) -> Result<&'k str, Candidates<'k>> {
+        let keys = self.command.map_or(&[][..], |info| info.keys);
+
+        match matching.find(keys, |info| info.name, key) {
+            Ok(info) => Ok(info.map_or(key, |info| info.name)),
+            Err(()) => Err(Candidates::keys(keys, key, matching)),
+        }
+    }
+
+    /// Notify the callback that the rest of the line is skipped because of the
+    /// error `kind` at the column `position` and return the next state.
+    fn fail<CB>(&mut self, kind: tokenizer::Error, position: usize, callback: &mut CB) -> MachineState
+        where CB: FnMut(CallbackCommand) {
+        let cmd = if self.current_cmd.is_empty() { None } else { Some(self.current_cmd.as_str()) };
+        callback(CallbackCommand::Error { cmd, kind, position });
+        self.errors = self.errors.wrapping_add(1);
+        self.reset_line();
+        MachineState::Aborted
+    }
+
+    /// Forget the command and key of the current line.
+    fn reset_line(&mut self) {
+        // assign rather than `clear`, which trips a debug assertion in heapless
+        self.current_cmd = String::new();
+        self.current_key = String::new();
+        self.arg_index = 0;
+        self.command = None;
+        self.subcommands = &[];
+    }
+
+    /// Start a new line with the command `name` at the column `column` of the
+    /// table `commands` and return the next state.
+    fn start_command<CB>(&mut self, commands: &'c [CommandInfo], name: &str, column: usize, callback: &mut CB) 
+        -> MachineState
+        where CB: FnMut(CallbackCommand) {
+        let command = match self.matching.find(commands, |info| info.name, name) {
+            Ok(command) => command,
+            Err(()) => {
+                callback(CallbackCommand::Ambiguous("", name, Candidates::commands(commands, name, self.matching)));
+                self.reset_line();
+                return MachineState::Aborted
+            },
+        };
+
+        // an abbreviated name may not fit into the buffer
+        if self.current_cmd.push_str(command.map_or(name, |info| info.name)).is_err() {
+            return self.fail(tokenizer::Error::Overflow, column, callback)
+        }
+        self.command = command;
+        self.subcommands = command.map_or(&[], |info| info.subcommands);
+        MachineState::Key
+    }
+
+    /// Append the pending word to the command if it names a subcommand and 
+    /// return whether it does. Fails with the next state if the word abbreviates
+    /// several subcommands or the path is too long to be stored, which is 
+    /// reported as an overflow at the column of the word.
+    fn enter_subcommand<CB>(&mut self, callback: &mut CB) -> Result<bool, MachineState> 
+        where CB: FnMut(CallbackCommand) {
+        let subcommands = self.subcommands;
+        let word = self.current_key.as_str();
+
+        let info = match self.matching.find(subcommands, |info| info.name, word) {
+            Ok(Some(info)) => info,
+            Ok(None) => return Ok(false),
+            Err(()) => {
+                let candidates = Candidates::commands(subcommands, word, self.matching);
+                callback(CallbackCommand::Ambiguous(self.current_cmd.as_str(), word, candidates));
+                self.reset_line();
+                return Err(MachineState::Aborted)
+            },
+        };
+
+        if self.current_cmd.len() + 1 + info.name.len() > self.current_cmd.capacity() {
+            return Err(self.fail(tokenizer::Error::Overflow, self.key_column, callback))
+        }
+        let _ = self.current_cmd.push(' ');
+        let _ = self.current_cmd.push_str(info.name);
+        self.arg_index = 0;
+        self.command = Some(info);
+        self.subcommands = info.subcommands;
+        Ok(true)
+    }
+
+    /// Emit the key value pair of the pending key and `value`. Fails with the 
+    /// next state if the key abbreviates several keys.
+    fn emit_attribute<CB>(&mut self, value: &str, callback: &mut CB) -> Result<(), MachineState> 
+        where CB: FnMut(CallbackCommand) {
+        match self.key_name(self.current_key.as_str(), self.matching) {
+            Ok(key) => callback(CallbackCommand::Attribute(self.current_cmd.as_str(), key, value)),
+            Err(candidates) => {
+                callback(CallbackCommand::Ambiguous(self.current_cmd.as_str(), self.current_key.as_str(), candidates));
+                self.reset_line();
+                return Err(MachineState::Aborted)
+            },
+        }
+        self.subcommands = &[];
+        Ok(())
+    }
+
+    /// Emit the pending word once it is known not to be followed by a value. 
+    /// Fails with the next state if the rest of the line has to be skipped.
+    fn finish_word<CB>(&mut self, callback: &mut CB) -> Result<(), MachineState> where CB: FnMut(CallbackCommand) {
+        match self.state {
+            MachineState::Word => {
+                if self.enter_subcommand(callback)? {
+                    return Ok(())
+                }
+                // flags are not abbreviated, as they may be positional arguments
+                let word = self.current_key.as_str();
+                let keys = self.command.map_or(&[][..], |info| info.keys);
+                match self.matching.exact().find(keys, |info| info.name, word) {
+                    // flags do not take up the index of a positional argument
+                    Ok(Some(key)) => callback(CallbackCommand::Argument(self.current_cmd.as_str(), self.arg_index, key.name)),
+                    _ => {
+                        callback(CallbackCommand::Argument(self.current_cmd.as_str(), self.arg_index, word));
+                        self.arg_index += 1;
+                    },
+                }
+                Ok(())
+            },
+            MachineState::Value => self.emit_attribute("", callback),
+            _ => Ok(()),
+        }
+    }
+
+    /// Emit the pending word and the command event at the end of the line.
+    fn end_line<CB>(&mut self, callback: &mut CB) where CB: FnMut(CallbackCommand) {
+        if self.finish_word(callback).is_ok() {
+            callback(CallbackCommand::Command(self.current_cmd.as_str()));
+        }
+        self.reset_line();
+    }
+
+    /// Parse the tokens of `tokenizer` into events for `callback`. Words naming
+    /// a subcommand of the table `commands` extend the command instead of 
+    /// being emitted as arguments.
+    pub fn parse_data<QLEN, CB>(&mut self, tokenizer: &mut Tokenizer<SLEN, QLEN>, commands: &'c [CommandInfo],
+        mut callback: CB) -> nb::Result<(), tokenizer::Error> 
+        where QLEN: ArrayLength<u8>, CB: FnMut(CallbackCommand) {
+        let res = tokenizer.get_tokens(|token| {
             let new_state = match token {
                 Token::NewLine => {
-                    if self.state != MachineState::NewCommandCR {
-                        callback(CallbackCommand::Command(self.current_cmd.as_str()));
-                        self.current_cmd.clear();
-                        self.current_key.clear();
+                    match self.state {
+                        MachineState::NewCommandCR | MachineState::Aborted => (),
+                        _ => self.end_line(&mut callback),
                     }
                     MachineState::NewCommand
                 },
                 Token::CarriageReturn => {
-                    callback(CallbackCommand::Command(self.current_cmd.as_str()));
-                    self.current_cmd.clear();
-                    self.current_key.clear();
+                    match self.state {
+                        MachineState::Aborted => (),
+                        _ => self.end_line(&mut callback),
+                    }
                     MachineState::NewCommandCR
                 }, // ignore carriage returns
-                Token::Value(s) => {
+                Token::Error(kind, position) => {
                     match self.state {
-                        MachineState::NewCommandCR => {
-                            self.current_cmd = String::from(s);
-                            MachineState::Key
-                        },
-                        MachineState::NewCommand => {
-                            self.current_cmd = String::from(s);
-                            MachineState::Key
-                        },
+                        MachineState::Aborted => MachineState::Aborted,
+                        _ => self.fail(kind, position, &mut callback),
+                    }
+                },
+                Token::Value(s, column) => {
+                    match self.state {
+                        MachineState::NewCommandCR => self.start_command(commands, s, column, &mut callback),
+                        MachineState::NewCommand => self.start_command(commands, s, column, &mut callback),
                         MachineState::Key => {
                             self.current_key = String::from(s);
-                            MachineState::Value
+                            self.key_column = column;
+                            MachineState::Word
+                        },
+                        MachineState::Word => {
+                            match self.finish_word(&mut callback) {
+                                Ok(()) => {
+                                    self.current_key = String::from(s);
+                                    self.key_column = column;
+                                    MachineState::Word
+                                },
+                                Err(state) => state,
+                            }
                         },
                         MachineState::Value => {
-                            callback(CallbackCommand::Attribute(self.current_cmd.as_str(), self.current_key.as_str(), s));
-                            MachineState::Key
+                            match self.emit_attribute(s, &mut callback) {
+                                Ok(()) => MachineState::Key,
+                                Err(state) => state,
+                            }
                         },
+                        MachineState::Aborted => MachineState::Aborted,
                     }
                 },
                 Token::Space => {
                     match self.state {
-                        MachineState::Value => {
-                            callback(CallbackCommand::Attribute(self.current_cmd.as_str(), self.current_key.as_str(), ""));
-                            MachineState::Key
+                        MachineState::Word | MachineState::Value => {
+                            match self.finish_word(&mut callback) {
+                                Ok(()) => MachineState::Key,
+                                Err(state) => state,
+                            }
                         },
                         MachineState::NewCommand => self.state.clone(),
                         MachineState::NewCommandCR => self.state.clone(),
-                        MachineState::Key => self.state.clone()
+                        MachineState::Key => self.state.clone(),
+                        MachineState::Aborted => self.state.clone(),
                     }
                 },
                 Token::Equals => {
-                    self.state.clone()
+                    match self.state {
+                        MachineState::Word => MachineState::Value,
+                        _ => self.state.clone(),
+                    }
                 }
             };
 
             self.state = new_state;
-        })
+        });
+
+        if let Err(nb::Error::Other(_)) = res {
+            // the rest of the line can no longer be trusted, so skip it
+            // without executing the command
+            self.reset_line();
+            self.state = MachineState::Aborted;
+        }
+
+        res
     }
 }
\ No newline at end of file
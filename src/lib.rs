@@ -75,11 +75,21 @@
 pub extern crate embedded_hal as hal;
 pub extern crate nb;
 pub extern crate heapless;
+#[cfg(feature = "derive")]
+extern crate light_cli_derive;
 
 #[macro_use]
 mod macros;
 mod tokenizer;
 mod lexer;
+mod registry;
+mod matching;
+mod value;
+mod constraint;
+mod parsed;
+mod command;
+mod typed;
+mod editor;
 mod output;
 mod input;
 
@@ -87,10 +97,21 @@ mod input;
 pub mod examples;
 
 #[cfg(test)]
+#[allow(clippy::assertions_on_constants)]
 mod tests;
 
 pub use lexer::CallbackCommand;
+pub use registry::{CommandInfo, KeyInfo};
+pub use matching::Candidates;
+pub use value::ParseValue;
+pub use constraint::{Constraint, MaxLen, Violation};
+pub use parsed::{ParsedArg, ParsedCommand};
+pub use tokenizer::{Encoding, Error as ParseError, FillError, OverrunPolicy};
 
-pub use output::LightCliOutput;
+pub use output::{LightCliOutput, Prompt, WriteMode};
 pub use input::LightCliInput;
+pub use command::{Command, Dispatcher, ErrorMessage, KeyError};
+pub use typed::{CommandParser, LightCommand};
+#[cfg(feature = "derive")]
+pub use light_cli_derive::LightCommand;
 
@@ -7,45 +7,173 @@
 /// * `$cmd`: The identifier to use to access the current command.
 /// * `$key`: The identifier to use to access the curernt key.
 /// * `$val`: The identifier to use to access the curernt value.
-/// * `$cmdv`: The name of the command.
-/// * `$keyv`: The key for command `$cmdv`.
+/// * `$cmdv`: The name of the command as a string literal.
+/// * `$keyv`: The key for command `$cmdv` as a string literal, or the index of a 
+///   positional argument in brackets, e.g. `[0]`.
+/// * `$ty`: (optional) The type to parse the value into, given after the key,
+///   e.g. `"Speed": u16`. The type has to implement [`ParseValue`].
+/// * `$constraint`: (optional) A [`Constraint`] the value has to fulfil, given 
+///   after the key and type, e.g. `"Duty": u8 where 0..=100`.
 /// * `$action`: What to do with the value `$val` for the given command and key.
+///   Keys may also be given as flags without a value, e.g. `RESET force`, 
+///   in which case `$val` is empty. If the key has a type, `$val` is the
+///   parsed value instead.
 /// * `$done`: What to do when the command is complete.
 /// * `$nomatch1`: What to do when the command value is not found 
-///             while trying to find a key action.
+///   while trying to find a key action.
 /// * `$nomatch2`: What to do when the key value is not found
-///             while trying to find a key action.
+///   while trying to find a key action.
 /// * `$nomatch3`: What to do when the command value is not found
-///             while trying to execute a command.
+///   while trying to execute a command.
+/// 
+/// # Handlers
+/// The following handlers may be given after `$nomatch3` in any order, each as
+/// its name followed by `=>` and an expression, e.g. 
+/// `missing => writeln!(out, "Missing {}", $key).unwrap()`. Handlers which are
+/// not given do nothing.
+/// * `unexpected`: What to do when the positional argument `$val`
+///   is not expected by the command.
+/// * `invalid`: What to do when the value `$val` of the key `$key`
+///   could not be parsed into the type of the key. For positional arguments
+///   `$key` is the index in brackets, e.g. `[0]`. `$done` is not executed for
+///   the line.
+/// * `rejected`: What to do when the value of the key `$key` 
+///   violates its constraint. `$val` is a [`Violation`] which displays the 
+///   reason, e.g. `out of range 0..100`. `$done` is not executed for the line.
+/// * `repeated`: What to do when the key `$key` is given more than
+///   once for the command `$cmd`. Only the first value is used and `$done` is
+///   not executed.
+/// * `missing`: What to do when the required key `$key` has not been
+///   given for the command `$cmd`. `$done` is not executed.
+/// * `ambiguous`: What to do when the abbreviation `$key` stands for
+///   several names, see [`set_abbreviations`]. `$cmd` is the command, which is
+///   empty if the command itself is abbreviated, and `$val` are the 
+///   [`Candidates`]. The rest of the line is skipped and `$done` is not executed.
+/// * `error`: What to do when the rest of the line has been skipped because of
+///   an error in the input, such as a value or the path of a subcommand which 
+///   does not fit into the string buffer. `$val` is the [`ParseError`], `$key` 
+///   the column of the line at which it occurred and `$cmd` the command, which
+///   is empty if it was not read. `$done` is not executed.
+/// 
+/// The messages [`lightcli!`] writes in these cases are created using 
+/// [`CallbackCommand::message`], e.g. 
+/// `CallbackCommand::MissingKey($cmd, $key).message()`, or using 
+/// [`CallbackCommand::key_message`] for invalid values.
+/// 
+/// # Breaking Changes
+/// `$cmdv` and `$keyv` used to be patterns. Since the names are collected into 
+/// the [`CommandInfo`] table, only string literals are accepted now, so entries
+/// such as `_` or `"A" | "B"` no longer compile. Unknown commands and keys are
+/// handled by `$nomatch1`, `$nomatch2` and `$nomatch3` instead of a `_` entry,
+/// and every alias needs an entry of its own.
+/// 
+/// The handlers following `$nomatch3` used to be given by their position. They
+/// are named now, e.g. `error => { ... }`, so that any of them can be given 
+/// without the ones in front of it.
+/// 
+/// # Flags
+/// A word without a value which matches one of the keys of the command is treated
+/// as a flag and takes precedence over positional arguments. Flags do not count
+/// towards the index of positional arguments, so they may be given before or
+/// after them, e.g. `RESET force 5` or `RESET 5 force`.
+/// 
+/// # Required Keys
+/// Keys and positional arguments are optional unless they are marked with 
+/// `#[required]` in front of them, e.g. `#[required] "addr" => ...`.
+/// 
+/// # Descriptions
+/// Commands, keys and positional arguments can be described using doc comments
+/// in front of them, e.g. `/// Set the name`. The descriptions are part of the
+/// [`CommandInfo`] table passed to [`set_commands`].
+/// 
+/// # Subcommands
+/// A group of subcommands is declared by giving a table in braces instead of 
+/// `=> $done`, optionally after the keys of the group itself:
+/// 
+/// ```ignore
+/// "NET" => {
+///     "IP" => {
+///         "SET" => [ "addr" => { ... } ] => { ... };
+///         "SHOW" => [] => { ... }
+///     }
+/// };
+/// "SENSOR" => [ [0]: u8 => { ... } ] {
+///     "CAL" => [ "offset": i16 => { ... } ] => { ... }
+/// }
+/// ```
+/// 
+/// The events of a subcommand carry its whole path as `$cmd`, e.g. `NET IP SET`,
+/// and its positional arguments are counted from zero again. The keys of a 
+/// group are handled before its subcommand is given, e.g. `3` in `SENSOR 3 CAL`.
+/// `$nomatch3` is executed for a group given without a subcommand and 
+/// `unexpected` for a word which is neither a subcommand nor an argument. In
+/// the latter case `$nomatch3` is not executed for the group.
 /// 
 /// [`LightCliInput`]: struct.LightCliInput.html
+/// [`CommandInfo`]: struct.CommandInfo.html
+/// [`ParseValue`]: trait.ParseValue.html
+/// [`Constraint`]: trait.Constraint.html
+/// [`Violation`]: struct.Violation.html
+/// [`Candidates`]: struct.Candidates.html
+/// [`ParseError`]: enum.ParseError.html
+/// [`CallbackCommand::message`]: enum.CallbackCommand.html#method.message
+/// [`CallbackCommand::key_message`]: enum.CallbackCommand.html#method.key_message
+/// [`set_abbreviations`]: struct.LightCliInput.html#method.set_abbreviations
 /// 
 /// # Remarks
 /// For a simpler way to write a command see the macro [`lightcli!`].
-/// This macro makes use of the underlying function [`parse_data`].
+/// This macro makes use of the underlying function [`parse_data_checked`]. It also
+/// passes the names of all commands and keys to [`set_commands`], so that
+/// they can be completed in interactive mode.
 /// 
-/// [`lightcli_adv!`]: macro.lightcli_adv.html
-/// [`parse_data`]: struct.LightCliInput.html#method.parse_data
+/// [`lightcli!`]: macro.lightcli.html
+/// [`parse_data_checked`]: struct.LightCliInput.html#method.parse_data_checked
+/// [`set_commands`]: struct.LightCliInput.html#method.set_commands
 #[macro_export]
 macro_rules! lightcli_adv {
-    ($cli:expr, $cmd:ident, $key:ident, $val:ident, [ 
-        $(
-            $cmdv:pat => [
-                $( $keyv:pat => $action:expr ),*
-            ] => $done:expr
-        );*
-    ], $nomatch1:expr, $nomatch2:expr, $nomatch3:expr) => {
-        let _ = $cli.parse_data(|cbcmd| {
+    (@registry $commands:ident, $cli:expr, $cmd:ident, $key:ident, $val:ident, [ $( $table:tt )* ], 
+        $after:expr, $nomatch1:expr, $nomatch2:expr, $nomatch3:expr, $unexpected:expr, 
+        $invalid:expr, $rejected:expr, $repeated:expr, $missing:expr, $ambiguous:expr, $error:expr) => {
+        #[allow(unused_variables)]
+        let $commands : &'static [$crate::CommandInfo] = $crate::__lightcli_table!(@info [] [] $( $table )*);
+        $cli.set_commands($commands);
+        $crate::__lightcli_table!(@flat ($cli, $cmd, $key, $val, $after, $nomatch1, $nomatch2, $nomatch3, 
+            $unexpected, $invalid, $rejected, $repeated, $missing, $ambiguous, $error) [ () ] [] $( $table )*);
+    };
+    (@dispatch $cli:expr, $cmd:ident, $key:ident, $val:ident, $after:expr, $nomatch1:expr, $nomatch2:expr, 
+        $nomatch3:expr, $unexpected:expr, $invalid:expr, $rejected:expr, $repeated:expr, 
+        $missing:expr, $ambiguous:expr, $error:expr; $( ( ( $( $path:tt )* ) [ $( $keys:tt )* ] $done:tt ) )*) => {
+        let _ = $cli.parse_data_checked(|cbcmd| {
+            // invalid and rejected values keep `$done` from being executed
+            #[allow(unused_mut)]
+            let mut accepted = true;
             match cbcmd {
+                #[allow(unused_variables)]
                 $crate::CallbackCommand::Attribute($cmd, $key, $val) => {
                     match $cmd {
                         $(
-                        $cmdv => {
-                            match $key {
-                                $(
-                                    $keyv => { $action },
-                                )*
-                                _ => $nomatch2,
+                        concat!($( $path )*) => {
+                            #[allow(unused_mut)]
+                            let mut handled = false;
+                            $crate::__lightcli_keys!((@attribute handled, $key, $val, { accepted = false; $invalid }, { accepted = false; $rejected };) [] [] false $( $keys )*);
+                            if !handled { $nomatch2 }
+                        }
+                        )*
+                        _ => $nomatch1,
+                    }
+                },
+                #[allow(unused_variables)]
+                $crate::CallbackCommand::Argument($cmd, index, $val) => {
+                    match $cmd {
+                        $(
+                        concat!($( $path )*) => {
+                            #[allow(unused_mut)]
+                            let mut handled = false;
+                            $crate::__lightcli_keys!((@flag handled, $key, $val, { accepted = false; $invalid }, { accepted = false; $rejected };) [] [] false $( $keys )*);
+                            $crate::__lightcli_keys!((@argument handled, index, $key, $val, { accepted = false; $invalid }, { accepted = false; $rejected };) [] [] false $( $keys )*);
+                            if !handled {
+                                $crate::__lightcli_table!(@unexpected $done, accepted);
+                                $unexpected
                             }
                         }
                         )*
@@ -55,14 +183,337 @@ macro_rules! lightcli_adv {
                 $crate::CallbackCommand::Command($cmd) => {
                     match $cmd {
                         $(
-                            $cmdv => $done,
+                            concat!($( $path )*) => $crate::__lightcli_table!(@done $done, $after, $nomatch3),
                         )*
                         _ => $nomatch3,
                     }
-                }
+                },
+                // only sent by `parse_commands`
+                $crate::CallbackCommand::TooManyArguments(_) => (),
+                #[allow(unused_variables)]
+                $crate::CallbackCommand::RepeatedKey($cmd, $key) => $repeated,
+                #[allow(unused_variables)]
+                $crate::CallbackCommand::MissingKey($cmd, $key) => $missing,
+                #[allow(unused_variables)]
+                $crate::CallbackCommand::Ambiguous($cmd, $key, $val) => $ambiguous,
+                $crate::CallbackCommand::Error { cmd, kind, position } => {
+                    #[allow(unused_variables)]
+                    let $cmd = cmd.unwrap_or("");
+                    #[allow(unused_variables)]
+                    let $key = position;
+                    #[allow(unused_variables)]
+                    let $val = kind;
+                    $error
+                },
             }
+            accepted
         });
     };
+    // fills the slots for unexpected, invalid, rejected, repeated, missing, 
+    // ambiguous and error with the named handlers
+    (@handlers $args:tt [ $u:tt $i:tt $r:tt $rep:tt $m:tt $a:tt $e:tt ] unexpected => $h:tt $( $rest:tt )*) => {
+        $crate::lightcli_adv!(@handlers $args [ $h $i $r $rep $m $a $e ] $( $rest )*)
+    };
+    (@handlers $args:tt [ $u:tt $i:tt $r:tt $rep:tt $m:tt $a:tt $e:tt ] invalid => $h:tt $( $rest:tt )*) => {
+        $crate::lightcli_adv!(@handlers $args [ $u $h $r $rep $m $a $e ] $( $rest )*)
+    };
+    (@handlers $args:tt [ $u:tt $i:tt $r:tt $rep:tt $m:tt $a:tt $e:tt ] rejected => $h:tt $( $rest:tt )*) => {
+        $crate::lightcli_adv!(@handlers $args [ $u $i $h $rep $m $a $e ] $( $rest )*)
+    };
+    (@handlers $args:tt [ $u:tt $i:tt $r:tt $rep:tt $m:tt $a:tt $e:tt ] repeated => $h:tt $( $rest:tt )*) => {
+        $crate::lightcli_adv!(@handlers $args [ $u $i $r $h $m $a $e ] $( $rest )*)
+    };
+    (@handlers $args:tt [ $u:tt $i:tt $r:tt $rep:tt $m:tt $a:tt $e:tt ] missing => $h:tt $( $rest:tt )*) => {
+        $crate::lightcli_adv!(@handlers $args [ $u $i $r $rep $h $a $e ] $( $rest )*)
+    };
+    (@handlers $args:tt [ $u:tt $i:tt $r:tt $rep:tt $m:tt $a:tt $e:tt ] ambiguous => $h:tt $( $rest:tt )*) => {
+        $crate::lightcli_adv!(@handlers $args [ $u $i $r $rep $m $h $e ] $( $rest )*)
+    };
+    (@handlers $args:tt [ $u:tt $i:tt $r:tt $rep:tt $m:tt $a:tt $e:tt ] error => $h:tt $( $rest:tt )*) => {
+        $crate::lightcli_adv!(@handlers $args [ $u $i $r $rep $m $a $h ] $( $rest )*)
+    };
+    (@handlers $args:tt $slots:tt $name:ident => $h:tt $( $rest:tt )*) => {
+        compile_error!(concat!("Unknown handler: ", stringify!($name)));
+    };
+    (@handlers ($commands:ident, $cli:expr, $cmd:ident, $key:ident, $val:ident, [ $($table:tt)* ], $after:expr,
+        $nomatch1:expr, $nomatch2:expr, $nomatch3:expr) [ $u:tt $i:tt $r:tt $rep:tt $m:tt $a:tt $e:tt ]) => {
+        $crate::lightcli_adv!(@registry $commands, $cli, $cmd, $key, $val, [ $($table)* ], $after,
+            $nomatch1, $nomatch2, $nomatch3, $u, $i, $r, $rep, $m, $a, $e
+        );
+    };
+    (@named $commands:ident, $cli:expr, $cmd:ident, $key:ident, $val:ident, [ $($table:tt)* ], $after:expr,
+        $nomatch1:expr, $nomatch2:expr, $nomatch3:expr $(, $name:ident => $handler:expr )* $(,)*) => {
+        $crate::lightcli_adv!(@handlers ($commands, $cli, $cmd, $key, $val, [ $($table)* ], $after, 
+            $nomatch1, $nomatch2, $nomatch3) [ {} {} {} {} {} {} {} ] $( $name => { $handler } )*);
+    };
+    ($cli:expr, $cmd:ident, $key:ident, $val:ident, [ $($table:tt)* ], 
+        $nomatch1:expr, $nomatch2:expr, $nomatch3:expr $(, $name:ident => $handler:expr )* $(,)*) => {
+        $crate::lightcli_adv!(@named commands, $cli, $cmd, $key, $val, [ $($table)* ], {}, 
+            $nomatch1, $nomatch2, $nomatch3 $(, $name => $handler )*);
+    };
+}
+
+/// Brings the entries of a command's key list into the form
+/// `([$desc, ...] $required $keyv ($ty) ($constraint) $action)` and passes them 
+/// on to [`__lightcli_entry!`] after the given arguments. Descriptions are written
+/// as doc comments in front of the entries and `$required` is true if the entry
+/// is marked `#[required]`. The type `$ty` is empty for untyped entries and 
+/// `$constraint` is empty for entries without a constraint.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __lightcli_keys {
+    (($($args:tt)*) [ $($entries:tt)* ] [ $($desc:expr),* ] $req:tt #[doc = $d:expr] $($rest:tt)*) => {
+        $crate::__lightcli_keys!(($($args)*) [ $($entries)* ] [ $($desc,)* $d ] $req $($rest)*)
+    };
+    (($($args:tt)*) [ $($entries:tt)* ] [ $($desc:expr),* ] $req:tt #[required] $($rest:tt)*) => {
+        $crate::__lightcli_keys!(($($args)*) [ $($entries)* ] [ $($desc),* ] true $($rest)*)
+    };
+    (($($args:tt)*) [ $($entries:tt)* ] $desc:tt $req:tt $keyv:tt => $action:expr , $($rest:tt)*) => {
+        $crate::__lightcli_keys!(($($args)*) [ $($entries)* ($desc $req $keyv () () $action) ] [] false $($rest)*)
+    };
+    (($($args:tt)*) [ $($entries:tt)* ] $desc:tt $req:tt $keyv:tt => $action:expr) => {
+        $crate::__lightcli_keys!(($($args)*) [ $($entries)* ($desc $req $keyv () () $action) ] [] false)
+    };
+    (($($args:tt)*) [ $($entries:tt)* ] $desc:tt $req:tt $keyv:tt where $c:expr => $action:expr , $($rest:tt)*) => {
+        $crate::__lightcli_keys!(($($args)*) [ $($entries)* ($desc $req $keyv () ($c) $action) ] [] false $($rest)*)
+    };
+    (($($args:tt)*) [ $($entries:tt)* ] $desc:tt $req:tt $keyv:tt where $c:expr => $action:expr) => {
+        $crate::__lightcli_keys!(($($args)*) [ $($entries)* ($desc $req $keyv () ($c) $action) ] [] false)
+    };
+    (($($args:tt)*) [ $($entries:tt)* ] $desc:tt $req:tt $keyv:tt : $ty:ty => $action:expr , $($rest:tt)*) => {
+        $crate::__lightcli_keys!(($($args)*) [ $($entries)* ($desc $req $keyv ($ty) () $action) ] [] false $($rest)*)
+    };
+    (($($args:tt)*) [ $($entries:tt)* ] $desc:tt $req:tt $keyv:tt : $ty:ty => $action:expr) => {
+        $crate::__lightcli_keys!(($($args)*) [ $($entries)* ($desc $req $keyv ($ty) () $action) ] [] false)
+    };
+    (($($args:tt)*) [ $($entries:tt)* ] $desc:tt $req:tt $keyv:tt : $ty:ty where $c:expr => $action:expr , $($rest:tt)*) => {
+        $crate::__lightcli_keys!(($($args)*) [ $($entries)* ($desc $req $keyv ($ty) ($c) $action) ] [] false $($rest)*)
+    };
+    (($($args:tt)*) [ $($entries:tt)* ] $desc:tt $req:tt $keyv:tt : $ty:ty where $c:expr => $action:expr) => {
+        $crate::__lightcli_keys!(($($args)*) [ $($entries)* ($desc $req $keyv ($ty) ($c) $action) ] [] false)
+    };
+    (($($args:tt)*) [ $($entries:tt)* ] [] false) => {
+        $crate::__lightcli_entry!($($args)* $($entries)*)
+    };
+}
+
+/// Expands the entries of a command table for the given event. Keys are
+/// matched against attributes and flags, bracketed indices against positional
+/// arguments. `@keys` and `@arguments` collect the keys and positional 
+/// arguments of a command together with their descriptions.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __lightcli_entry {
+    (@attribute $handled:ident, $key:ident, $val:ident, $invalid:expr, $rejected:expr; 
+        $( ($desc:tt $req:tt $keyv:tt $ty:tt $c:tt $action:expr) )*) => {
+        $( $crate::__lightcli_entry!(@attribute_one $handled, $key, $val, $invalid, $rejected, $keyv, $ty, $c, $action); )*
+    };
+    (@attribute_one $handled:ident, $key:ident, $val:ident, $invalid:expr, $rejected:expr, 
+        [$index:expr], $ty:tt, $c:tt, $action:expr) => {};
+    (@attribute_one $handled:ident, $key:ident, $val:ident, $invalid:expr, $rejected:expr, 
+        $keyv:expr, $ty:tt, $c:tt, $action:expr) => {
+        if !$handled && $key == $keyv {
+            $handled = true;
+            $crate::__lightcli_entry!(@parse $val, $val, $ty, $c, $action, $invalid, $rejected)
+        }
+    };
+    (@flag $handled:ident, $key:ident, $val:ident, $invalid:expr, $rejected:expr; 
+        $( ($desc:tt $req:tt $keyv:tt $ty:tt $c:tt $action:expr) )*) => {
+        $( $crate::__lightcli_entry!(@flag_one $handled, $key, $val, $invalid, $rejected, $keyv, $ty, $c, $action); )*
+    };
+    (@flag_one $handled:ident, $key:ident, $val:ident, $invalid:expr, $rejected:expr, 
+        [$index:expr], $ty:tt, $c:tt, $action:expr) => {};
+    (@flag_one $handled:ident, $key:ident, $val:ident, $invalid:expr, $rejected:expr, 
+        $keyv:expr, (), $c:tt, $action:expr) => {
+        if !$handled && $val == $keyv {
+            $handled = true;
+            #[allow(unused_variables)]
+            let $key : &str = $val;
+            #[allow(unused_variables)]
+            let $val = "";
+            $crate::__lightcli_entry!(@check $val, str, $val, $c, $action, $rejected)
+        }
+    };
+    (@flag_one $handled:ident, $key:ident, $val:ident, $invalid:expr, $rejected:expr, 
+        $keyv:expr, ($ty:ty), $c:tt, $action:expr) => {
+        if !$handled && $val == $keyv {
+            $handled = true;
+            #[allow(unused_variables)]
+            let $key : &str = $val;
+            #[allow(unused_variables)]
+            let $val = "";
+            match <$ty as $crate::ParseValue>::from_flag() {
+                Some($val) => $crate::__lightcli_entry!(@check $val, $ty, &$val, $c, $action, $rejected),
+                None => $invalid,
+            }
+        }
+    };
+    (@argument $handled:ident, $index:ident, $key:ident, $val:ident, $invalid:expr, $rejected:expr; 
+        $( ($desc:tt $req:tt $keyv:tt $ty:tt $c:tt $action:expr) )*) => {
+        $( $crate::__lightcli_entry!(@argument_one $handled, $index, $key, $val, $invalid, $rejected, $keyv, $ty, $c, $action); )*
+    };
+    (@argument_one $handled:ident, $index:ident, $key:ident, $val:ident, $invalid:expr, $rejected:expr, 
+        [$i:expr], $ty:tt, $c:tt, $action:expr) => {
+        if !$handled && $index == $i {
+            $handled = true;
+            #[allow(unused_variables)]
+            let $key : &str = concat!("[", stringify!($i), "]");
+            $crate::__lightcli_entry!(@parse $val, $val, $ty, $c, $action, $invalid, $rejected)
+        }
+    };
+    (@argument_one $handled:ident, $index:ident, $key:ident, $val:ident, $invalid:expr, $rejected:expr, 
+        $keyv:expr, $ty:tt, $c:tt, $action:expr) => {};
+    (@parse $val:ident, $input:expr, (), $c:tt, $action:expr, $invalid:expr, $rejected:expr) => {
+        $crate::__lightcli_entry!(@check $val, str, $input, $c, $action, $rejected)
+    };
+    (@parse $val:ident, $input:expr, ($ty:ty), $c:tt, $action:expr, $invalid:expr, $rejected:expr) => {
+        match <$ty as $crate::ParseValue>::parse_value($input) {
+            Some($val) => $crate::__lightcli_entry!(@check $val, $ty, &$val, $c, $action, $rejected),
+            None => $invalid,
+        }
+    };
+    (@check $val:ident, $ty:ty, $value:expr, (), $action:expr, $rejected:expr) => {
+        $action
+    };
+    (@check $val:ident, $ty:ty, $value:expr, ($c:expr), $action:expr, $rejected:expr) => {{
+        let constraint = $c;
+        if $crate::Constraint::<$ty>::check(&constraint, $value) {
+            $action
+        } else {
+            #[allow(unused_variables)]
+            let $val = $crate::Violation::<$ty>(&constraint);
+            $rejected
+        }
+    }};
+    (@keys [ $( $keys:expr ),* ]) => { &[ $( $keys ),* ] };
+    (@keys [ $( $keys:expr ),* ] ($desc:tt $req:tt [$index:expr] $ty:tt $c:tt $action:expr) $( $rest:tt )*) => {
+        $crate::__lightcli_entry!(@keys [ $( $keys ),* ] $( $rest )*)
+    };
+    (@keys [ $( $keys:expr ),* ] ([ $( $desc:expr ),* ] $req:tt $keyv:tt $ty:tt $c:tt $action:expr) $( $rest:tt )*) => {
+        $crate::__lightcli_entry!(@keys [ 
+            $( $keys, )* 
+            $crate::KeyInfo { name: $keyv, description: concat!($( $desc ),*), required: $req } 
+        ] $( $rest )*)
+    };
+    (@arguments [ $( $args:expr ),* ]) => { &[ $( $args ),* ] };
+    (@arguments [ $( $args:expr ),* ] ([ $( $desc:expr ),* ] $req:tt [$index:expr] $ty:tt $c:tt $action:expr) $( $rest:tt )*) => {
+        $crate::__lightcli_entry!(@arguments [ 
+            $( $args, )* 
+            $crate::KeyInfo { 
+                name: concat!("[", stringify!($index), "]"), 
+                description: concat!($( $desc ),*),
+                required: $req,
+            }
+        ] $( $rest )*)
+    };
+    (@arguments [ $( $args:expr ),* ] ($desc:tt $req:tt $keyv:tt $ty:tt $c:tt $action:expr) $( $rest:tt )*) => {
+        $crate::__lightcli_entry!(@arguments [ $( $args ),* ] $( $rest )*)
+    };
+}
+
+/// Walks a command table with nested groups of subcommands. `@info` builds the
+/// tree of [`CommandInfo`] entries, while `@flat` flattens the table into 
+/// entries `(($path) [$keys] ($done))` and passes them on to `lightcli_adv!`. 
+/// The path is a list of literals for `concat!`, e.g. `"NET", " ", "IP"`, and
+/// `$done` is empty for groups. The stack holds the path prefix of every 
+/// enclosing group, the end of a group is marked by `@pop`. `@done` completes
+/// an entry, while `@unexpected` rejects the line of a group given with a word 
+/// which is not one of its subcommands.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __lightcli_table {
+    (@info [ $( $infos:expr ),* ] [ $( $desc:expr ),* ] #[doc = $d:expr] $( $rest:tt )*) => {
+        $crate::__lightcli_table!(@info [ $( $infos ),* ] [ $( $desc, )* $d ] $( $rest )*)
+    };
+    (@info [ $( $infos:expr ),* ] $desc:tt ; $( $rest:tt )*) => {
+        $crate::__lightcli_table!(@info [ $( $infos ),* ] $desc $( $rest )*)
+    };
+    (@info [ $( $infos:expr ),* ] [ $( $desc:expr ),* ] $cmdv:literal => [ $( $keys:tt )* ] => $done:expr ; $( $rest:tt )*) => {
+        $crate::__lightcli_table!(@info [ $( $infos, )* $crate::__lightcli_table!(@one [ $( $desc ),* ] $cmdv [ $( $keys )* ] []) ] [] $( $rest )*)
+    };
+    (@info [ $( $infos:expr ),* ] [ $( $desc:expr ),* ] $cmdv:literal => [ $( $keys:tt )* ] => $done:expr) => {
+        $crate::__lightcli_table!(@info [ $( $infos, )* $crate::__lightcli_table!(@one [ $( $desc ),* ] $cmdv [ $( $keys )* ] []) ] [])
+    };
+    (@info [ $( $infos:expr ),* ] [ $( $desc:expr ),* ] $cmdv:literal => { $( $sub:tt )* } $( $rest:tt )*) => {
+        $crate::__lightcli_table!(@info [ $( $infos, )* $crate::__lightcli_table!(@one [ $( $desc ),* ] $cmdv [] [ $( $sub )* ]) ] [] $( $rest )*)
+    };
+    (@info [ $( $infos:expr ),* ] [ $( $desc:expr ),* ] $cmdv:literal => [ $( $keys:tt )* ] { $( $sub:tt )* } $( $rest:tt )*) => {
+        $crate::__lightcli_table!(@info [ $( $infos, )* $crate::__lightcli_table!(@one [ $( $desc ),* ] $cmdv [ $( $keys )* ] [ $( $sub )* ]) ] [] $( $rest )*)
+    };
+    (@info [ $( $infos:expr ),* ] []) => { &[ $( $infos ),* ] };
+    (@one [ $( $desc:expr ),* ] $cmdv:literal [ $( $keys:tt )* ] [ $( $sub:tt )* ]) => {
+        $crate::CommandInfo {
+            name: $cmdv,
+            description: concat!($( $desc ),*),
+            keys: $crate::__lightcli_keys!((@keys []) [] [] false $( $keys )*),
+            arguments: $crate::__lightcli_keys!((@arguments []) [] [] false $( $keys )*),
+            subcommands: $crate::__lightcli_table!(@info [] [] $( $sub )*),
+        }
+    };
+    (@flat $args:tt $stack:tt [ $( $entries:tt )* ] # [ $( $attr:tt )* ] $( $rest:tt )*) => {
+        $crate::__lightcli_table!(@flat $args $stack [ $( $entries )* ] $( $rest )*)
+    };
+    (@flat $args:tt $stack:tt [ $( $entries:tt )* ] ; $( $rest:tt )*) => {
+        $crate::__lightcli_table!(@flat $args $stack [ $( $entries )* ] $( $rest )*)
+    };
+    (@flat $args:tt [ ( $( $prefix:tt )* ) $( $stack:tt )* ] [ $( $entries:tt )* ] 
+        $cmdv:literal => [ $( $keys:tt )* ] => $done:expr ; $( $rest:tt )*) => {
+        $crate::__lightcli_table!(@flat $args [ ( $( $prefix )* ) $( $stack )* ] 
+            [ $( $entries )* ( ( $( $prefix )* $cmdv ) [ $( $keys )* ] ($done) ) ] $( $rest )*)
+    };
+    (@flat $args:tt [ ( $( $prefix:tt )* ) $( $stack:tt )* ] [ $( $entries:tt )* ] 
+        $cmdv:literal => [ $( $keys:tt )* ] => $done:expr) => {
+        $crate::__lightcli_table!(@flat $args [ ( $( $prefix )* ) $( $stack )* ] 
+            [ $( $entries )* ( ( $( $prefix )* $cmdv ) [ $( $keys )* ] ($done) ) ])
+    };
+    (@flat $args:tt [ ( $( $prefix:tt )* ) $( $stack:tt )* ] [ $( $entries:tt )* ] 
+        $cmdv:literal => { $( $sub:tt )* } $( $rest:tt )*) => {
+        $crate::__lightcli_table!(@flat $args [ ( $( $prefix )* $cmdv, " ", ) ( $( $prefix )* ) $( $stack )* ] 
+            [ $( $entries )* ( ( $( $prefix )* $cmdv ) [] () ) ] $( $sub )* ; @pop $( $rest )*)
+    };
+    (@flat $args:tt [ ( $( $prefix:tt )* ) $( $stack:tt )* ] [ $( $entries:tt )* ] 
+        $cmdv:literal => [ $( $keys:tt )* ] { $( $sub:tt )* } $( $rest:tt )*) => {
+        $crate::__lightcli_table!(@flat $args [ ( $( $prefix )* $cmdv, " ", ) ( $( $prefix )* ) $( $stack )* ] 
+            [ $( $entries )* ( ( $( $prefix )* $cmdv ) [ $( $keys )* ] () ) ] $( $sub )* ; @pop $( $rest )*)
+    };
+    (@flat $args:tt [ $top:tt $( $stack:tt )* ] [ $( $entries:tt )* ] @pop $( $rest:tt )*) => {
+        $crate::__lightcli_table!(@flat $args [ $( $stack )* ] [ $( $entries )* ] $( $rest )*)
+    };
+    (@flat ( $( $args:tt )* ) [ () ] [ $( $entries:tt )* ]) => {
+        $crate::lightcli_adv!(@dispatch $( $args )*; $( $entries )*)
+    };
+    (@done ($done:expr), $after:expr, $nomatch:expr) => {{
+        $done;
+        $after
+    }};
+    (@done (), $after:expr, $nomatch:expr) => {
+        $nomatch
+    };
+    // rejects a command named HELP in the table of `lightcli!`
+    (@no_help) => {};
+    (@no_help # [ $( $attr:tt )* ] $( $rest:tt )*) => {
+        $crate::__lightcli_table!(@no_help $( $rest )*)
+    };
+    (@no_help ; $( $rest:tt )*) => {
+        $crate::__lightcli_table!(@no_help $( $rest )*)
+    };
+    (@no_help "HELP" => $( $rest:tt )*) => {
+        compile_error!("The command HELP is added by lightcli! and cannot be given in its table");
+    };
+    (@no_help $cmdv:literal => [ $( $keys:tt )* ] => $done:expr ; $( $rest:tt )*) => {
+        $crate::__lightcli_table!(@no_help $( $rest )*)
+    };
+    (@no_help $cmdv:literal => [ $( $keys:tt )* ] => $done:expr) => {};
+    (@no_help $cmdv:literal => { $( $sub:tt )* } $( $rest:tt )*) => {
+        $crate::__lightcli_table!(@no_help $( $rest )*)
+    };
+    (@no_help $cmdv:literal => [ $( $keys:tt )* ] { $( $sub:tt )* } $( $rest:tt )*) => {
+        $crate::__lightcli_table!(@no_help $( $rest )*)
+    };
+    (@unexpected ($done:expr), $accepted:ident) => {};
+    (@unexpected (), $accepted:ident) => {
+        $accepted = false;
+    };
 }
 
 
@@ -74,40 +525,168 @@ macro_rules! lightcli_adv {
 /// * `$cmd`: The identifier to use to access the current command.
 /// * `$key`: The identifier to use to access the curernt key.
 /// * `$val`: The identifier to use to access the curernt value.
-/// * `$cmdv`: The name of the command.
-/// * `$keyv`: The key for command `$cmdv`.
+/// * `$cmdv`: The name of the command as a string literal.
+/// * `$keyv`: The key for command `$cmdv` as a string literal, or the index of a
+///   positional argument in brackets, e.g. `[0]`.
+/// * `$ty`: (optional) The type to parse the value into, given after the key,
+///   e.g. `"Speed": u16`. The type has to implement [`ParseValue`].
+/// * `$constraint`: (optional) A [`Constraint`] the value has to fulfil, given 
+///   after the key and type, e.g. `"Duty": u8 where 0..=100`. Values violating
+///   it are reported as e.g. `Duty out of range 0..=100`.
 /// * `$action`: What to do with the value `$val` for the given command and key.
-/// * `$done`: What to do when the command is complete.
+/// * `$done`: What to do when the command is complete. It is not executed if
+///   a value was invalid or violated its constraint.
 /// 
 /// [`LightCliInput`]: struct.LightCliInput.html
 /// [`LightCliOutput`]: struct.LightCliOutput.html
+/// [`ParseValue`]: trait.ParseValue.html
+/// [`Constraint`]: trait.Constraint.html
 /// 
 /// # Remarks
 /// For a command that doesn't use the output and allows for custom 
 /// error handling see the macro [`lightcli_adv!`]. This macro makes use
 /// of the underlying function [`parse_data`].
 /// 
+/// The prompt set using [`set_prompt`] is printed after every command or 
+/// error message, once all of the received data has been parsed.
+/// 
+/// Keys can be marked as required as described for [`lightcli_adv!`]. If a 
+/// required key is missing or a key is repeated, an error is printed instead 
+/// of executing `$done`. The same holds for an ambiguous abbreviation, e.g.
+/// `Ambiguous command: HE (HELLO, HELP)`, if abbreviations have been enabled
+/// using [`set_abbreviations`]. Errors in the input are reported with the 
+/// column at which they occurred, e.g. `Line too long at column 33`.
+/// 
+/// The command `HELP` is added automatically. It lists all commands, while
+/// `HELP <cmd>` lists the keys and positional arguments of a single command. 
+/// Descriptions for both are taken from doc comments in front of the entries, 
+/// as described for [`lightcli_adv!`]. A table which defines `HELP` itself 
+/// does not compile, use [`lightcli_adv!`] for a `HELP` command of your own:
+/// 
+/// ```compile_fail
+/// # #[macro_use] extern crate light_cli;
+/// # use std::fmt::Write;
+/// # use light_cli::{LightCliInput, LightCliOutput};
+/// # use light_cli::heapless::consts::U32;
+/// # fn run<E>(mut cli: LightCliInput<U32>, mut out: LightCliOutput<E>) {
+/// lightcli!(cli, out, cmd, key, val, [
+///     "HELP" => [] => {}
+/// ]);
+/// # }
+/// # fn main() {}
+/// ```
+/// 
+/// As described for [`lightcli_adv!`], commands and keys have to be given as
+/// string literals rather than patterns.
+/// 
+/// Groups of subcommands are declared as described for [`lightcli_adv!`]. A 
+/// group given without a subcommand prints its help, which lists the 
+/// subcommands, and an unknown word after a group is reported as an unknown
+/// subcommand. `HELP NET IP` describes the subcommand `IP` of `NET`.
+/// 
 /// [`lightcli_adv!`]: macro.lightcli_adv.html
 /// [`parse_data`]: struct.LightCliInput.html#method.parse_data
+/// [`set_prompt`]: struct.LightCliOutput.html#method.set_prompt
+/// [`set_abbreviations`]: struct.LightCliInput.html#method.set_abbreviations
 #[macro_export]
 macro_rules! lightcli {
-    ($cli:expr, $cl_out:expr, $cmd:ident, $key:ident, $val:ident, [ 
-        $(
-            $cmdv:pat => [
-                $( $keyv:pat => $action:expr ),*
-            ] => $done:expr
-        );*
-    ]) => {
-        lightcli_adv!($cli, $cmd, $key, $val, [
-                $(
-                    $cmdv => [
-                        $( $keyv => $action ),*
-                    ] => $done
-                );*
+    ($cli:expr, $cl_out:expr, $cmd:ident, $key:ident, $val:ident, [ $( $table:tt )* ]) => {
+        $crate::__lightcli_table!(@no_help $( $table )*);
+        // kept by the input, since a line may be split across several calls
+        #[allow(unused_mut)]
+        let mut help = $cli.help_topic();
+        $crate::lightcli_adv!(@named commands, $cli, $cmd, $key, $val, [
+                $( $table )*;
+                /// Show the available commands or describe a single command.
+                "HELP" => [
+                    /// The command to describe.
+                    [0] => {
+                        help.given = true;
+                        help.info = help.find(commands, $val);
+                        if help.info.is_none() {
+                            writeln!($cl_out, "{}", 
+                                $crate::CallbackCommand::Command($val).message()).unwrap();
+                        }
+                    }
+                ] => {
+                    match help.info {
+                        Some(info) => info.write_help(&mut $cl_out).unwrap(),
+                        None if !help.given => $crate::CommandInfo::write_list(commands, &mut $cl_out).unwrap(),
+                        None => (),
+                    }
+                    help = Default::default();
+                }
             ], 
+            $cl_out.request_prompt(),
             {}, 
-            {writeln!($cl_out, "Unknown key for command {}: {}", $cmd, $key).unwrap()}, 
-            {writeln!($cl_out, "Unknown command: {}", $cmd).unwrap()}
+            {
+                writeln!($cl_out, "{}", 
+                    $crate::CallbackCommand::Attribute($cmd, $key, $val).message()).unwrap();
+                $cl_out.request_prompt();
+            }, 
+            {
+                match $crate::CommandInfo::find(commands, $cmd) {
+                    Some(info) => info.write_help(&mut $cl_out).unwrap(),
+                    None => writeln!($cl_out, "{}", 
+                        $crate::CallbackCommand::Command($cmd).message()).unwrap(),
+                }
+                $cl_out.request_prompt();
+            },
+            unexpected => {
+                if $cmd == "HELP" && help.given {
+                    if let Some(info) = help.info {
+                        help.info = help.find(info.subcommands, $val);
+                        if help.info.is_none() {
+                            writeln!($cl_out, "{}", 
+                                $crate::CallbackCommand::Argument(info.name, 0, $val).subcommand_message()).unwrap();
+                        }
+                    }
+                } else {
+                    match $crate::CommandInfo::find(commands, $cmd) {
+                        Some(info) if !info.subcommands.is_empty() => writeln!($cl_out, "{}", 
+                            $crate::CallbackCommand::Argument($cmd, 0, $val).subcommand_message()).unwrap(),
+                        _ => writeln!($cl_out, "{}", 
+                            $crate::CallbackCommand::Argument($cmd, 0, $val).message()).unwrap(),
+                    }
+                    $cl_out.request_prompt();
+                }
+            },
+            invalid => {
+                writeln!($cl_out, "{}", 
+                    $crate::CallbackCommand::Attribute($cmd, $key, $val).key_message($crate::KeyError::Invalid)).unwrap();
+                $cl_out.request_prompt();
+            },
+            rejected => {
+                writeln!($cl_out, "{} {}", $key, $val).unwrap();
+                $cl_out.request_prompt();
+            },
+            repeated => {
+                help = Default::default();
+                writeln!($cl_out, "{}", 
+                    $crate::CallbackCommand::RepeatedKey($cmd, $key).message()).unwrap();
+                $cl_out.request_prompt();
+            },
+            missing => {
+                help = Default::default();
+                writeln!($cl_out, "{}", 
+                    $crate::CallbackCommand::MissingKey($cmd, $key).message()).unwrap();
+                $cl_out.request_prompt();
+            },
+            ambiguous => {
+                help = Default::default();
+                writeln!($cl_out, "{}", 
+                    $crate::CallbackCommand::Ambiguous($cmd, $key, $val).message()).unwrap();
+                $cl_out.request_prompt();
+            },
+            error => {
+                help = Default::default();
+                let cmd = if $cmd.is_empty() { None } else { Some($cmd) };
+                let error = $crate::CallbackCommand::Error { cmd, kind: $val, position: $key };
+                writeln!($cl_out, "{}", error.message()).unwrap();
+                $cl_out.request_prompt();
+            }
         );
+        $cli.set_help_topic(help);
+        let _ = $cl_out.show_prompt();
     };
-}
\ No newline at end of file
+}
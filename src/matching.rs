@@ -0,0 +1,108 @@
+use core::fmt;
+
+use registry::{CommandInfo, KeyInfo};
+
+/// Describes how the names of commands and keys typed on the command line are
+/// matched against the table of commands.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct Matching {
+    /// Ignore ASCII case, so that `hello` matches `HELLO`.
+    pub ignore_case: bool,
+    /// Accept a unique prefix of a name, so that `HEL` matches `HELLO`.
+    pub abbreviations: bool,
+}
+
+impl Matching {
+    /// The same matching without abbreviations.
+    pub fn exact(self) -> Self {
+        Self { abbreviations: false, ..self }
+    }
+
+    /// Returns true if `input` is the name `name`.
+    fn is(&self, name: &str, input: &str) -> bool {
+        self.starts_with(name, input) && name.len() == input.len()
+    }
+
+    /// Returns true if `input` is the beginning of the name `name`.
+    pub fn starts_with(&self, name: &str, input: &str) -> bool {
+        match name.as_bytes().get(..input.len()) {
+            Some(head) if self.ignore_case => head.eq_ignore_ascii_case(input.as_bytes()),
+            Some(head) => head == input.as_bytes(),
+            None => false,
+        }
+    }
+
+    /// Returns true if `input` may stand for the name `name`.
+    fn abbreviates(&self, name: &str, input: &str) -> bool {
+        if self.abbreviations && !input.is_empty() {
+            self.starts_with(name, input)
+        } else {
+            self.is(name, input)
+        }
+    }
+
+    /// Find the entry of `items` whose name is given by `input`. A name given
+    /// in full is preferred over abbreviations, of which there may only be one.
+    /// Returns `Err` if `input` abbreviates several names.
+    pub fn find<'a, T, F>(&self, items: &'a [T], name: F, input: &str) -> Result<Option<&'a T>, ()>
+        where F: Fn(&T) -> &'static str {
+        if let Some(item) = items.iter().find(|item| self.is(name(item), input)) {
+            return Ok(Some(item))
+        }
+
+        let mut found = items.iter().filter(|item| self.abbreviates(name(item), input));
+        match (found.next(), found.next()) {
+            (item, None) => Ok(item),
+            _ => Err(()),
+        }
+    }
+}
+
+/// The names of the commands or keys an abbreviation may stand for. They are
+/// displayed separated by commas, e.g. `HELLO, HELP`.
+#[derive(Clone, Copy)]
+pub struct Candidates<'a> {
+    commands: &'a [CommandInfo],
+    keys: &'a [KeyInfo],
+    abbreviation: &'a str,
+    matching: Matching,
+}
+
+impl<'a> Candidates<'a> {
+    /// The commands of `commands` abbreviated by `abbreviation`.
+    pub(crate) fn commands(commands: &'a [CommandInfo], abbreviation: &'a str, matching: Matching) -> Self {
+        Self { commands, keys: &[], abbreviation, matching }
+    }
+
+    /// The keys of `keys` abbreviated by `abbreviation`.
+    pub(crate) fn keys(keys: &'a [KeyInfo], abbreviation: &'a str, matching: Matching) -> Self {
+        Self { commands: &[], keys, abbreviation, matching }
+    }
+
+    /// Returns true if the abbreviation stands for a key rather than a command
+    /// or subcommand.
+    pub fn is_key(&self) -> bool {
+        !self.keys.is_empty()
+    }
+
+    /// The names the abbreviation may stand for.
+    pub fn names(&self) -> impl Iterator<Item = &'static str> + 'a {
+        let Candidates { commands, keys, abbreviation, matching } = *self;
+
+        commands.iter().map(|info| info.name)
+            .chain(keys.iter().map(|info| info.name))
+            .filter(move |name| matching.abbreviates(name, abbreviation))
+    }
+}
+
+impl<'a> fmt::Display for Candidates<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, name) in self.names().enumerate() {
+            if i > 0 {
+                f.write_str(", ")?;
+            }
+            f.write_str(name)?;
+        }
+        Ok(())
+    }
+}
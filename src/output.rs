@@ -3,29 +3,77 @@ use core;
 use nb;
 use hal::serial::Write;
 
+use heapless::ArrayLength;
 use heapless::consts::*;
 use heapless::spsc::Queue;
 
-pub struct LightCliOutput<'a, E: 'a> {
-    rb: Queue<u8, U128>,
-    writer: &'a mut Write<u8, Error=E>
+/// Specifies what happens when writing to a full output buffer.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WriteMode {
+    /// Keep flushing the buffer until all data has been written.
+    Blocking,
+    /// Never wait for the serial device. Whatever does not fit into the
+    /// buffer is dropped and counted.
+    NonBlocking,
 }
 
-impl<'a, E> core::fmt::Write for LightCliOutput<'a, E> {
+/// The prompt printed when the command line is ready for the next command.
+#[derive(Clone, Copy)]
+pub enum Prompt<'a> {
+    /// Don't print a prompt.
+    None,
+    /// Print the given string, e.g. `Prompt::Static("stm32> ")`.
+    Static(&'a str),
+    /// Let the closure write the prompt, e.g. to show the current mode. As the
+    /// closure is borrowed by the output, changing state has to be shared
+    /// using e.g. a `Cell`.
+    Dynamic(&'a dyn Fn(&mut dyn core::fmt::Write) -> core::fmt::Result),
+}
+
+/// Counts the bytes written to it, used to measure formatted output.
+struct Counter(usize);
+
+impl core::fmt::Write for Counter {
     fn write_str(&mut self, s: &str) -> core::fmt::Result {
-        for c in s.as_bytes() {
-            loop {
-                if self.rb.enqueue(c.clone()).is_ok() {
-                    break;
-                } else {
-                    match self.flush() {
-                        Err(nb::Error::Other(_)) => return Err(core::fmt::Error),
-                        _ => () // otherwise either non blocking or ok, so try to repeat
-                    }
-                }
+        self.0 += s.len();
+        Ok(())
+    }
+}
+
+/// The buffered command line output.
+/// 
+/// # Type Parameters
+/// * `E` - The error type of the serial output.
+/// * `OLEN` - The number of bytes that can be buffered until the output 
+///   is flushed, 128 by default.
+pub struct LightCliOutput<'a, E: 'a, OLEN = U128> where OLEN: ArrayLength<u8> {
+    rb: Queue<u8, OLEN>,
+    writer: &'a mut dyn Write<u8, Error=E>,
+    mode: WriteMode,
+    dropped: usize,
+    prompt: Prompt<'a>,
+    prompt_requested: bool,
+}
+
+impl<'a, E, OLEN> core::fmt::Write for LightCliOutput<'a, E, OLEN> where OLEN: ArrayLength<u8> {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        if self.mode == WriteMode::NonBlocking {
+            if let Err(nb::Error::Other(_)) = self.flush() {
+                return Err(core::fmt::Error)
+            }
+
+            // only write complete characters
+            let mut end = core::cmp::min(self.free(), s.len());
+            while !s.is_char_boundary(end) {
+                end -= 1;
             }
+
+            self.enqueue(&s.as_bytes()[..end]);
+            self.dropped = self.dropped.wrapping_add(s.len() - end);
+            return Ok(())
         }
-        Ok(())
+
+        self.write_bytes(s.as_bytes())
     }
 }
 
@@ -35,19 +83,207 @@ impl<'a, E> LightCliOutput<'a, E> {
     /// # Arguments
     /// * `writer`: The serial output instance, implementing the [`Write<u8>`] interface.
     /// 
+    /// # Remarks
+    /// 
+    /// The output buffer holds 128 bytes, use [`new_sized`] for a different size.
+    /// 
+    /// [`Write<u8>`]: ../embedded_hal/serial/trait.Write.html
+    /// [`new_sized`]: struct.LightCliOutput.html#method.new_sized
+    pub fn new(writer: &'a mut dyn Write<u8, Error = E>) -> Self {
+        Self::new_sized(writer)
+    }
+}
+
+impl<'a, E, OLEN> LightCliOutput<'a, E, OLEN> where OLEN: ArrayLength<u8> {
+    /// Creates a now buffered console output instance with an output buffer 
+    /// of `OLEN` bytes, e.g. `let cl_out: LightCliOutput<_, U32> = LightCliOutput::new_sized(tx)`.
+    /// 
+    /// # Arguments
+    /// * `writer`: The serial output instance, implementing the [`Write<u8>`] interface.
+    /// 
     /// [`Write<u8>`]: ../embedded_hal/serial/trait.Write.html
-    pub fn new(writer: &'a mut Write<u8, Error = E>) -> Self {
+    pub fn new_sized(writer: &'a mut dyn Write<u8, Error = E>) -> Self {
         Self {
             rb: Queue::new(),
-            writer: writer
+            writer,
+            mode: WriteMode::Blocking,
+            dropped: 0,
+            prompt: Prompt::None,
+            prompt_requested: false,
         }
     }
 
-    fn peek(&self) -> Option<u8> {
-        match self.rb.iter().next() {
-            None => None,
-            Some(v) => Some(v.clone())
+    /// Set how writes behave when the output buffer is full. By default 
+    /// writing blocks until everything has been buffered.
+    /// 
+    /// # Arguments
+    /// * `mode`: The write mode to use for all further writes.
+    pub fn set_write_mode(&mut self, mode: WriteMode) {
+        self.mode = mode;
+    }
+
+    /// The number of bytes dropped in non blocking mode because the buffer
+    /// was full.
+    pub fn dropped(&self) -> usize {
+        self.dropped
+    }
+
+    /// Reset the number of dropped bytes returned by [`dropped`] to zero.
+    /// 
+    /// [`dropped`]: struct.LightCliOutput.html#method.dropped
+    pub fn reset_dropped(&mut self) {
+        self.dropped = 0;
+    }
+
+    /// Set the prompt which is printed whenever the command line is ready for
+    /// the next command. By default there is no prompt.
+    /// 
+    /// # Arguments
+    /// * `prompt`: The prompt to print from now on.
+    /// 
+    /// # Remarks
+    /// The prompt is printed for the first time by the next call to [`show_prompt`],
+    /// which happens at the end of [`lightcli!`]. The macro requests it again 
+    /// after every command and error.
+    /// 
+    /// [`show_prompt`]: struct.LightCliOutput.html#method.show_prompt
+    /// [`lightcli!`]: macro.lightcli.html
+    pub fn set_prompt(&mut self, prompt: Prompt<'a>) {
+        self.prompt = prompt;
+        self.prompt_requested = true;
+    }
+
+    /// Request the prompt to be printed by the next call to [`show_prompt`].
+    /// 
+    /// [`show_prompt`]: struct.LightCliOutput.html#method.show_prompt
+    pub fn request_prompt(&mut self) {
+        self.prompt_requested = true;
+    }
+
+    /// Print the prompt if it has been requested since it was last printed.
+    pub fn show_prompt(&mut self) -> core::fmt::Result {
+        if !self.prompt_requested {
+            return Ok(())
         }
+
+        self.prompt_requested = false;
+        match self.prompt {
+            Prompt::None => Ok(()),
+            Prompt::Static(s) => core::fmt::Write::write_str(self, s),
+            Prompt::Dynamic(f) => f(self),
+        }
+    }
+
+    fn enqueue(&mut self, bytes: &[u8]) {
+        for b in bytes {
+            // the caller ensures that there is enough space
+            let _ = self.rb.enqueue(*b);
+        }
+    }
+
+    /// Write raw bytes according to the write mode. Unlike `write_str` this
+    /// may split characters in non blocking mode.
+    pub(crate) fn write_bytes(&mut self, bytes: &[u8]) -> core::fmt::Result {
+        if self.mode == WriteMode::NonBlocking {
+            if let Err(nb::Error::Other(_)) = self.flush() {
+                return Err(core::fmt::Error)
+            }
+
+            let end = core::cmp::min(self.free(), bytes.len());
+            self.enqueue(&bytes[..end]);
+            self.dropped = self.dropped.wrapping_add(bytes.len() - end);
+            return Ok(())
+        }
+
+        for c in bytes {
+            loop {
+                if self.rb.enqueue(*c).is_ok() {
+                    break;
+                } else if let Err(nb::Error::Other(_)) = self.flush() {
+                    return Err(core::fmt::Error)
+                }
+                // otherwise either non blocking or ok, so try to repeat
+            }
+        }
+        Ok(())
+    }
+
+    /// Make sure that `len` bytes can be buffered, flushing if required.
+    fn reserve(&mut self, len: usize) -> nb::Result<(), E> {
+        if len > self.free() {
+            if let Err(nb::Error::Other(o)) = self.flush() {
+                return Err(nb::Error::Other(o))
+            }
+            if len > self.free() {
+                return Err(nb::Error::WouldBlock)
+            }
+        }
+        Ok(())
+    }
+
+    /// Write the string only if it completely fits into the output buffer.
+    /// 
+    /// # Remarks
+    /// 
+    /// This never blocks, regardless of the write mode. If the string does not fit
+    /// even after trying to flush the buffer, nothing is written and `WouldBlock`
+    /// is returned. Strings longer than the [`capacity`] can never be written 
+    /// this way.
+    /// 
+    /// [`capacity`]: struct.LightCliOutput.html#method.capacity
+    pub fn try_write_str(&mut self, s: &str) -> nb::Result<(), E> {
+        self.reserve(s.len())?;
+        self.enqueue(s.as_bytes());
+        Ok(())
+    }
+
+    /// Write the formatted message only if it completely fits into the output
+    /// buffer, e.g. `cl_out.try_write_fmt(format_args!("Name={}\n", name))`.
+    /// 
+    /// # Remarks
+    /// 
+    /// The message is formatted twice, first to determine its length and then to
+    /// write it. Otherwise this behaves like [`try_write_str`].
+    /// 
+    /// [`try_write_str`]: struct.LightCliOutput.html#method.try_write_str
+    pub fn try_write_fmt(&mut self, args: core::fmt::Arguments) -> nb::Result<(), E> {
+        let mut counter = Counter(0);
+        // writing to the counter never fails
+        let _ = core::fmt::write(&mut counter, args);
+
+        self.reserve(counter.0)?;
+
+        // there is enough space, so this will not block or drop anything
+        let mode = self.mode;
+        self.mode = WriteMode::NonBlocking;
+        let _ = core::fmt::write(self, args);
+        self.mode = mode;
+        Ok(())
+    }
+
+    /// The total number of bytes the output buffer can hold.
+    pub fn capacity(&self) -> usize {
+        self.rb.capacity()
+    }
+
+    /// The number of bytes currently waiting to be sent.
+    pub fn len(&self) -> usize {
+        self.rb.len()
+    }
+
+    /// Returns true if there are no bytes waiting to be sent.
+    pub fn is_empty(&self) -> bool {
+        self.rb.is_empty()
+    }
+
+    /// The number of bytes that can be written without having to wait for
+    /// the buffer to be flushed.
+    pub fn free(&self) -> usize {
+        self.capacity() - self.len()
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.rb.iter().next().cloned()
     }
 
     /// Tries to send as many characters as it can until the interface
@@ -67,7 +303,7 @@ impl<'a, E> LightCliOutput<'a, E> {
             match co {
                 None => return Ok(()),
                 Some(c) => {
-                    let res = self.writer.write(c.clone());
+                    let res = self.writer.write(c);
                     match res {
                         Err(nb::Error::WouldBlock) => return Err(nb::Error::WouldBlock),
                         Err(nb::Error::Other(o)) => return Err(nb::Error::Other(o)),
@@ -0,0 +1,129 @@
+use heapless::{ArrayLength, String, Vec};
+
+use lexer::CallbackCommand;
+
+/// A key value pair such as `state=on` or a word without a value such as `3` 
+/// or the flag `force` of a [`ParsedCommand`].
+/// 
+/// [`ParsedCommand`]: struct.ParsedCommand.html
+#[derive(Clone, Debug)]
+pub struct ParsedArg<SLEN> where SLEN: ArrayLength<u8> {
+    key: String<SLEN>,
+    // a number rather than a bool or an `Option` for the key, as heapless 
+    // creates vectors from uninitialised memory, which is invalid for them
+    has_key: u8,
+    /// The value or the word.
+    pub value: String<SLEN>,
+}
+
+impl<SLEN> ParsedArg<SLEN> where SLEN: ArrayLength<u8> {
+    /// A key value pair.
+    fn pair(key: &str, value: &str) -> Self {
+        Self { key: String::from(key), has_key: 1, value: String::from(value) }
+    }
+
+    /// A word without a value.
+    fn word(word: &str) -> Self {
+        Self { key: String::new(), has_key: 0, value: String::from(word) }
+    }
+
+    /// The key, or `None` for a word without a value. The key of `""=x` is empty.
+    pub fn key(&self) -> Option<&str> {
+        if self.has_key != 0 { Some(self.key.as_str()) } else { None }
+    }
+
+    /// Returns true if this is a word without a value.
+    pub fn is_word(&self) -> bool {
+        self.key().is_none()
+    }
+}
+
+/// A complete line, collected by [`parse_commands`] so that a command can be
+/// executed once all of its arguments are known.
+/// 
+/// # Type Parameters
+/// * `SLEN` - The maximum length of a command, key or value.
+/// * `ALEN` - The maximum number of key value pairs and words of a line.
+/// 
+/// [`parse_commands`]: struct.LightCliInput.html#method.parse_commands
+#[derive(Clone, Debug)]
+pub struct ParsedCommand<SLEN, ALEN> 
+where SLEN: ArrayLength<u8>, ALEN: ArrayLength<ParsedArg<SLEN>> {
+    /// The name of the command.
+    pub name: String<SLEN>,
+    /// The key value pairs and words in the order they were given.
+    pub args: Vec<ParsedArg<SLEN>, ALEN>,
+    overflow: bool,
+}
+
+impl<SLEN, ALEN> Default for ParsedCommand<SLEN, ALEN>
+where SLEN: ArrayLength<u8>, ALEN: ArrayLength<ParsedArg<SLEN>> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<SLEN, ALEN> ParsedCommand<SLEN, ALEN>
+where SLEN: ArrayLength<u8>, ALEN: ArrayLength<ParsedArg<SLEN>> {
+    /// Create an empty command.
+    pub fn new() -> Self {
+        Self {
+            name: String::new(),
+            args: Vec::new(),
+            overflow: false,
+        }
+    }
+
+    /// The value of the key `key`, if it has been given.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.args.iter().find(|arg| arg.key() == Some(key))
+            .map(|arg| arg.value.as_str())
+    }
+
+    /// The word given as positional argument `index`, if any.
+    pub fn argument(&self, index: usize) -> Option<&str> {
+        self.words().nth(index)
+    }
+
+    /// Returns true if the word `flag` has been given.
+    pub fn has_flag(&self, flag: &str) -> bool {
+        self.words().any(|word| word == flag)
+    }
+
+    /// The words without a value in the order they were given.
+    fn words(&self) -> impl Iterator<Item = &str> {
+        self.args.iter().filter(|arg| arg.is_word()).map(|arg| arg.value.as_str())
+    }
+
+    /// Add the arguments of `event` to the command and pass the command on to
+    /// `callback` once the line is complete and valid. Repeated and missing 
+    /// keys are expected to have been checked for the events already.
+    pub(crate) fn collect<CB>(&mut self, event: CallbackCommand, callback: &mut CB) 
+        where CB: FnMut(Result<&ParsedCommand<SLEN, ALEN>, CallbackCommand>) {
+        let arg = match event {
+            CallbackCommand::Attribute(_, key, val) => 
+                ParsedArg::pair(key, val),
+            CallbackCommand::Argument(_, _, word) => 
+                ParsedArg::word(word),
+            CallbackCommand::Command(cmd) => {
+                if self.overflow {
+                    callback(Err(CallbackCommand::TooManyArguments(cmd)));
+                } else {
+                    self.name = String::from(cmd);
+                    callback(Ok(self));
+                }
+                *self = Self::new();
+                return;
+            },
+            event => {
+                *self = Self::new();
+                callback(Err(event));
+                return;
+            },
+        };
+
+        if self.args.push(arg).is_err() {
+            self.overflow = true;
+        }
+    }
+}
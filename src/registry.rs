@@ -0,0 +1,303 @@
+use core::fmt::{Result, Write};
+use core::ptr;
+
+use lexer::CallbackCommand;
+use matching::Matching;
+
+/// Describes a command accepted by the command line. A table of all commands 
+/// is generated by the [`lightcli!`] and [`lightcli_adv!`] macros and used to
+/// complete names in interactive mode and to answer `HELP`.
+/// 
+/// [`lightcli!`]: macro.lightcli.html
+/// [`lightcli_adv!`]: macro.lightcli_adv.html
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CommandInfo {
+    /// The name of the command.
+    pub name: &'static str,
+    /// The description of the command, which may be empty.
+    pub description: &'static str,
+    /// The keys accepted by the command.
+    pub keys: &'static [KeyInfo],
+    /// The positional arguments accepted by the command, named by their index
+    /// in brackets, e.g. `[0]`.
+    pub arguments: &'static [KeyInfo],
+    /// The subcommands of the command, e.g. `SET` for `NET IP SET`, which follow 
+    /// it separated by spaces.
+    pub subcommands: &'static [CommandInfo],
+}
+
+/// Describes a key or positional argument of a command.
+#[derive(Clone, Copy, Debug)]
+pub struct KeyInfo {
+    /// The name of the key.
+    pub name: &'static str,
+    /// The description of the key, which may be empty.
+    pub description: &'static str,
+    /// Whether the key has to be given for the command to be executed.
+    pub required: bool,
+}
+
+/// The command the automatic `HELP` command of [`lightcli!`] has been asked to 
+/// describe. It is kept by [`LightCliInput`], since a line may be parsed over
+/// several calls.
+/// 
+/// [`lightcli!`]: macro.lightcli.html
+/// [`LightCliInput`]: struct.LightCliInput.html
+#[doc(hidden)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct HelpTopic<'c> {
+    /// Whether a command has been given to `HELP` on the current line.
+    pub given: bool,
+    /// The entry of the command given, if it is known.
+    pub info: Option<&'c CommandInfo>,
+    /// How the names given to `HELP` are matched, as for the rest of the line.
+    matching: Matching,
+}
+
+impl<'c> HelpTopic<'c> {
+    pub(crate) fn new(help: HelpTopic<'c>, matching: Matching) -> Self {
+        Self { matching, ..help }
+    }
+
+    /// The command of `commands` named by `name`, which may be abbreviated or
+    /// differ in case as allowed for commands on the line.
+    pub fn find(&self, commands: &'c [CommandInfo], name: &str) -> Option<&'c CommandInfo> {
+        self.matching.find(commands, |info| info.name, name).ok()?
+    }
+}
+
+/// Tracks the keys and positional arguments given on the current line, so that
+/// repeated and missing keys can be reported. Only the first 64 keys and 
+/// positional arguments of a command are tracked.
+#[derive(Default)]
+pub(crate) struct SeenKeys<'c> {
+    seen: u64,
+    repeated: Option<&'static str>,
+    /// The command the keys were seen for, which changes with every subcommand.
+    command: Option<&'c CommandInfo>,
+    /// Whether the line has already been ended because a group of subcommands
+    /// was left with a repeated or missing key, so that its remaining events
+    /// are dropped.
+    ended: bool,
+}
+
+/// Write a line consisting of a name padded to `width` and its description.
+/// Required keys are marked after the description.
+fn write_entry(out: &mut dyn Write, name: &str, description: &str, required: bool, width: usize) -> Result {
+    let description = description.trim();
+    let marker = if required { "(required)" } else { "" };
+
+    if description.is_empty() && marker.is_empty() {
+        writeln!(out, "  {}", name)
+    } else if description.is_empty() || marker.is_empty() {
+        writeln!(out, "  {:width$}  {}{}", name, description, marker, width = width)
+    } else {
+        writeln!(out, "  {:width$}  {} {}", name, description, marker, width = width)
+    }
+}
+
+/// Returns true if `a` and `b` are the same entry of the table.
+fn same(a: Option<&CommandInfo>, b: Option<&CommandInfo>) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) => ptr::eq(a, b),
+        (None, None) => true,
+        _ => false,
+    }
+}
+
+/// Returns true if `name` is the name of the positional argument `index`, e.g. `[0]`.
+fn is_argument(name: &str, index: usize) -> bool {
+    name.starts_with('[') && name.ends_with(']') && 
+        name[1..name.len() - 1].parse() == Ok(index)
+}
+
+impl CommandInfo {
+    /// Find the command called `name` in the table `commands`. The name may be
+    /// the path of a subcommand separated by spaces, e.g. `NET IP SET`.
+    pub fn find<'a>(commands: &'a [CommandInfo], name: &str) -> Option<&'a CommandInfo> {
+        let mut path = name.split(' ');
+        let first = path.next()?;
+        let info = commands.iter().find(|info| info.name == first)?;
+
+        path.try_fold(info, |info, name| info.subcommands.iter().find(|sub| sub.name == name))
+    }
+
+    /// Write a listing of all commands in `commands` with their descriptions.
+    pub fn write_list(commands: &[CommandInfo], out: &mut dyn Write) -> Result {
+        let width = commands.iter().map(|info| info.name.len()).max().unwrap_or(0);
+
+        writeln!(out, "Commands:")?;
+        for info in commands {
+            write_entry(out, info.name, info.description, false, width)?;
+        }
+        Ok(())
+    }
+
+    /// Write the description of the command followed by a listing of its 
+    /// positional arguments, keys and subcommands.
+    pub fn write_help(&self, out: &mut dyn Write) -> Result {
+        let description = self.description.trim();
+
+        if description.is_empty() {
+            writeln!(out, "{}", self.name)?;
+        } else {
+            writeln!(out, "{}: {}", self.name, description)?;
+        }
+
+        let entries = self.arguments.iter().chain(self.keys);
+        let width = entries.clone().map(|key| key.name.len()).max().unwrap_or(0);
+
+        for key in entries {
+            write_entry(out, key.name, key.description, key.required, width)?;
+        }
+
+        if !self.subcommands.is_empty() {
+            let width = self.subcommands.iter().map(|info| info.name.len()).max().unwrap_or(0);
+
+            writeln!(out, "Subcommands:")?;
+            for info in self.subcommands {
+                write_entry(out, info.name, info.description, false, width)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The position of the key `key` among the keys followed by the positional
+    /// arguments of the command.
+    fn key_position(&self, key: &str) -> Option<usize> {
+        self.keys.iter().position(|info| info.name == key)
+    }
+
+    /// The position of the positional argument `index` among the keys followed
+    /// by the positional arguments of the command.
+    fn argument_position(&self, index: usize) -> Option<usize> {
+        self.arguments.iter().position(|info| is_argument(info.name, index))
+            .map(|position| self.keys.len() + position)
+    }
+}
+
+impl<'c> SeenKeys<'c> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Switch to the command `cmd` of the table `commands`, which is a 
+    /// subcommand if it differs from the current one. The groups of 
+    /// subcommands left on the way are checked as at the end of the line and 
+    /// the keys seen for them are forgotten. Returns false if a group had a 
+    /// repeated or missing key, which ends the line.
+    fn enter<CB>(&mut self, commands: &'c [CommandInfo], cmd: &str, callback: &mut CB) -> bool
+        where CB: FnMut(CallbackCommand) {
+        let info = CommandInfo::find(commands, cmd);
+        if same(info, self.command) {
+            return true
+        }
+
+        // groups before the current command have been checked already
+        let mut reached = self.command.is_none();
+        let mut valid = true;
+        for (end, _) in cmd.match_indices(' ') {
+            let group = &cmd[..end];
+            let group_info = CommandInfo::find(commands, group);
+            if same(group_info, self.command) {
+                reached = true;
+                valid &= Self::report(group, group_info, self.seen, self.repeated, callback);
+            } else if reached {
+                valid &= Self::report(group, group_info, 0, None, callback);
+            }
+        }
+
+        self.seen = 0;
+        self.repeated = None;
+        self.command = info;
+        self.ended = !valid;
+        valid
+    }
+
+    /// Pass on a repeated key event for `repeated` and a missing key event for
+    /// every required key of `info` which is not in `seen`. Returns true if 
+    /// there were none.
+    fn report<CB>(cmd: &str, info: Option<&CommandInfo>, seen: u64, repeated: Option<&str>, 
+        callback: &mut CB) -> bool where CB: FnMut(CallbackCommand) {
+        if let Some(key) = repeated {
+            callback(CallbackCommand::RepeatedKey(cmd, key));
+        }
+        let mut valid = repeated.is_none();
+
+        if let Some(info) = info {
+            let entries = info.keys.iter().chain(info.arguments).take(64);
+            for (position, key) in entries.enumerate() {
+                if key.required && seen & (1u64 << position) == 0 {
+                    callback(CallbackCommand::MissingKey(cmd, key.name));
+                    valid = false;
+                }
+            }
+        }
+        valid
+    }
+
+    /// Mark the entry of the current command at `position` as seen and return 
+    /// false if it has already been seen on the current line.
+    fn insert(&mut self, position: Option<usize>) -> bool {
+        let (info, position) = match (self.command, position) {
+            (Some(info), Some(position)) if position < 64 => (info, position),
+            _ => return true,
+        };
+
+        let bit = 1u64 << position;
+        if self.seen & bit != 0 {
+            if self.repeated.is_none() {
+                self.repeated = info.keys.iter().chain(info.arguments).nth(position).map(|key| key.name);
+            }
+            return false;
+        }
+        self.seen |= bit;
+        true
+    }
+
+    /// Pass `event` on to `callback` unless it repeats a key of the current line.
+    /// At the end of the line a repeated key event is passed on for the first 
+    /// repeated key and a missing key event for every required key which has 
+    /// not been given, followed by the command event only if there were none. 
+    /// The same is done for a group of subcommands once one of its subcommands
+    /// is given, in which case the rest of the line is dropped if the group 
+    /// had a repeated or missing key. Every line therefore ends with a command,
+    /// repeated key, missing key, ambiguous or error event.
+    pub fn check<CB>(&mut self, commands: &'c [CommandInfo], event: CallbackCommand, callback: &mut CB)
+        where CB: FnMut(CallbackCommand) {
+        match event {
+            CallbackCommand::Attribute(..) | CallbackCommand::Argument(..) if self.ended => (),
+            _ if self.ended => *self = Self::new(),
+            CallbackCommand::Attribute(cmd, key, val) => {
+                if self.enter(commands, cmd, callback) {
+                    let position = self.command.and_then(|info| info.key_position(key));
+                    if self.insert(position) {
+                        callback(CallbackCommand::Attribute(cmd, key, val));
+                    }
+                }
+            },
+            CallbackCommand::Argument(cmd, index, word) => {
+                if self.enter(commands, cmd, callback) {
+                    let position = self.command.and_then(|info| 
+                        info.key_position(word).or_else(|| info.argument_position(index)));
+                    if self.insert(position) {
+                        callback(CallbackCommand::Argument(cmd, index, word));
+                    }
+                }
+            },
+            CallbackCommand::Command(cmd) => {
+                let entered = self.enter(commands, cmd, callback);
+                let (info, seen, repeated) = (self.command, self.seen, self.repeated);
+                *self = Self::new();
+
+                if entered && Self::report(cmd, info, seen, repeated, callback) {
+                    callback(CallbackCommand::Command(cmd));
+                }
+            },
+            event => {
+                *self = Self::new();
+                callback(event);
+            },
+        }
+    }
+}
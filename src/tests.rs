@@ -1,15 +1,34 @@
 
+use core::cell::Cell;
+use core::fmt::Write;
+use hal::serial;
 use hal::serial::Read;
 use heapless::consts::*;
-use heapless::RingBuffer;
+use heapless::spsc::Queue;
 use heapless::String;
 use nb;
+use tokenizer;
 
 use LightCliInput;
+use LightCliOutput;
+use Prompt;
+use WriteMode;
 use CallbackCommand;
+use Encoding;
+use FillError;
+use OverrunPolicy;
+use ParseValue;
+use MaxLen;
+use CommandInfo;
+use KeyInfo;
+use ParsedCommand;
+use Command;
+use Dispatcher;
+use KeyError;
+use heapless::ArrayLength;
 
 pub struct SerialBufferDevice {
-    pub rb: RingBuffer<u8, U512>,
+    pub rb: Queue<u8, U512>,
 }
 
 #[allow(dead_code)]
@@ -47,10 +66,48 @@ impl SerialBufferDevice {
     }
 }
 
+pub struct SerialOutputDevice {
+    pub rb: Queue<u8, U512>,
+    pub blocked: bool,
+}
+
+impl serial::Write<u8> for SerialOutputDevice {
+    type Error = Error;
+
+    fn write(&mut self, word: u8) -> nb::Result<(), Self::Error> {
+        if self.blocked {
+            return Err(nb::Error::WouldBlock)
+        }
+        self.rb.enqueue(word).unwrap();
+        Ok(())
+    }
+
+    fn flush(&mut self) -> nb::Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+impl SerialOutputDevice {
+    pub fn new() -> Self {
+        Self {
+            rb: Queue::new(),
+            blocked: false,
+        }
+    }
+
+    pub fn read_str(&mut self) -> String<U512> {
+        let mut s = String::new();
+        while let Some(b) = self.rb.dequeue() {
+            s.push(char::from(b)).unwrap();
+        }
+        s
+    }
+}
+
 #[test]
 pub fn test1() {
     let mut sb = SerialBufferDevice {
-        rb: RingBuffer::new()
+        rb: Queue::new()
     };
     let mut cli : LightCliInput<U32> = LightCliInput::new();
 
@@ -72,7 +129,8 @@ pub fn test1() {
                 assert!(cmd == "HELLO", "cmd={}", cmd);
                 assert!(!done);
                 done = true;
-            }
+            },
+            _ => assert!(false, "Unexpected event."),
         }
     });
     
@@ -83,7 +141,7 @@ pub fn test1() {
 #[test]
 pub fn test_win() {
     let mut sb = SerialBufferDevice {
-        rb: RingBuffer::new()
+        rb: Queue::new()
     };
     let mut cli : LightCliInput<U32> = LightCliInput::new();
 
@@ -105,7 +163,8 @@ pub fn test_win() {
                 assert!(cmd == "HELLO", "cmd={}", cmd);
                 assert!(!done);
                 done = true;
-            }
+            },
+            _ => assert!(false, "Unexpected event."),
         }
     });
     
@@ -117,7 +176,7 @@ pub fn test_win() {
 #[test]
 pub fn test_partial() {
     let mut sb = SerialBufferDevice {
-        rb: RingBuffer::new()
+        rb: Queue::new()
     };
     let mut cli : LightCliInput<U32> = LightCliInput::new();
 
@@ -139,7 +198,8 @@ pub fn test_partial() {
             },
             CallbackCommand::Command(_cmd) => {
                 assert!(false, "Command isn't finished.");
-            }
+            },
+            _ => assert!(false, "Unexpected event."),
         }
     });
 
@@ -162,7 +222,8 @@ pub fn test_partial() {
             CallbackCommand::Command(cmd) => {
                 assert!(cmd == "HELLO", "cmd={}", cmd);
                 done = true;
-            }
+            },
+            _ => assert!(false, "Unexpected event."),
         }
     });
 
@@ -174,7 +235,7 @@ pub fn test_partial() {
 pub fn test_macro() {
 
     let mut sb = SerialBufferDevice {
-        rb: RingBuffer::new()
+        rb: Queue::new()
     };
     let mut cli : LightCliInput<U32> = LightCliInput::new();
 
@@ -204,3 +265,1534 @@ pub fn test_macro() {
     assert!(ran);
     assert!(done);
 }
+
+#[test]
+pub fn test_quoted() {
+    let mut sb = SerialBufferDevice {
+        rb: Queue::new()
+    };
+    let mut cli : LightCliInput<U32> = LightCliInput::new();
+
+    sb.write_str("SETNAME Name=\"Hello World\" Data='a=b \\\'c\\x41' Empty=\"\"\n");
+    cli.fill(&mut sb).unwrap();
+
+    let mut count = 0;
+    let mut done = false;
+
+    let _ = cli.parse_data(|cbcmd| {
+        match cbcmd {
+            CallbackCommand::Attribute(cmd, key, val) => {
+                assert!(cmd == "SETNAME", "cmd={}", cmd);
+                match key {
+                    "Name" => assert!(val == "Hello World", "val={}", val),
+                    "Data" => assert!(val == "a=b 'cA", "val={}", val),
+                    "Empty" => assert!(val.is_empty(), "val={}", val),
+                    _ => assert!(false, "key={}", key),
+                }
+                count += 1;
+            },
+            CallbackCommand::Command(cmd) => {
+                assert!(cmd == "SETNAME", "cmd={}", cmd);
+                done = true;
+            },
+            _ => assert!(false, "Unexpected event."),
+        }
+    });
+
+    assert!(count == 3, "count={}", count);
+    assert!(done);
+}
+
+#[test]
+pub fn test_quotes_in_words() {
+    let mut sb = SerialBufferDevice {
+        rb: Queue::new()
+    };
+    let mut cli : LightCliInput<U32> = LightCliInput::new();
+
+    sb.write_str("SAY msg=don't name=O'Brien\n");
+    sb.write_str("SAY a\"b c\"d\n");
+    cli.fill(&mut sb).unwrap();
+
+    let values = ["don't", "O'Brien", "a\"b", "c\"d"];
+    let mut count = 0;
+    let mut done = 0;
+
+    let _ = cli.parse_data(|cbcmd| {
+        match cbcmd {
+            CallbackCommand::Attribute("SAY", _, val) | CallbackCommand::Argument("SAY", _, val) => {
+                assert!(val == values[count], "val={}", val);
+                count += 1;
+            },
+            CallbackCommand::Command("SAY") => done += 1,
+            _ => assert!(false, "Unexpected event."),
+        }
+    });
+
+    assert!(count == 4, "count={}", count);
+    assert!(done == 2, "done={}", done);
+}
+
+#[test]
+pub fn test_unterminated_quote() {
+    let mut sb = SerialBufferDevice {
+        rb: Queue::new()
+    };
+    let mut cli : LightCliInput<U32> = LightCliInput::new();
+
+    sb.write_str("SETNAME Name=\"Hello\r\nEHLO\r\n");
+    cli.fill(&mut sb).unwrap();
+
+    let mut done = false;
+    let mut errors = 0;
+
+    let res = cli.parse_data(|cbcmd| {
+        match cbcmd {
+            CallbackCommand::Attribute(_, _, _) => assert!(false, "Value is unterminated."),
+            CallbackCommand::Command(_) => assert!(false, "Command was aborted."),
+            CallbackCommand::Error { cmd, kind, position } => {
+                // the error is reported at the opening quote
+                assert!(cmd == Some("SETNAME"), "cmd={:?}", cmd);
+                assert!(kind == tokenizer::Error::UnterminatedQuote, "kind={:?}", kind);
+                assert!(position == 14, "position={}", position);
+                errors += 1;
+            },
+            _ => assert!(false, "Unexpected event."),
+        }
+    });
+
+    assert!(errors == 1, "errors={}", errors);
+
+    match res {
+        Err(nb::Error::Other(tokenizer::Error::UnterminatedQuote)) => (),
+        _ => assert!(false, "Expected an unterminated quote error."),
+    }
+
+    let _ = cli.parse_data(|cbcmd| {
+        match cbcmd {
+            CallbackCommand::Attribute(_, _, _) => assert!(false, "No attributes expected."),
+            CallbackCommand::Command(cmd) => {
+                assert!(cmd == "EHLO", "cmd={}", cmd);
+                assert!(!done);
+                done = true;
+            },
+            _ => assert!(false, "Unexpected event."),
+        }
+    });
+
+    assert!(done);
+}
+
+#[test]
+pub fn test_overflow() {
+    let mut sb = SerialBufferDevice {
+        rb: Queue::new()
+    };
+    let mut cli : LightCliInput<U8> = LightCliInput::new();
+
+    sb.write_str("HELLO Name=ABCDEFGHIJ X=Y\nABCDEFGHIJ\r\nEHLO\n");
+    cli.fill(&mut sb).unwrap();
+
+    let mut discarded = 0;
+    let mut done = false;
+
+    let _ = cli.parse_data(|cbcmd| {
+        match cbcmd {
+            CallbackCommand::Attribute(_, key, _) => assert!(false, "key={}", key),
+            CallbackCommand::Command(cmd) => {
+                assert!(discarded == 2, "discarded={}", discarded);
+                assert!(cmd == "EHLO", "cmd={}", cmd);
+                done = true;
+            },
+            CallbackCommand::Error { cmd, kind: tokenizer::Error::Overflow, position } => {
+                // the first character which does not fit
+                match discarded {
+                    0 => assert!(cmd == Some("HELLO") && position == 20, "position={}", position),
+                    _ => assert!(cmd.is_none() && position == 9, "position={}", position),
+                }
+                discarded += 1;
+            },
+            _ => assert!(false, "Unexpected event."),
+        }
+    });
+
+    assert!(discarded == 2, "discarded={}", discarded);
+    assert!(cli.errors() == 2, "errors={}", cli.errors());
+    assert!(done);
+}
+
+#[test]
+pub fn test_parse_errors() {
+    let mut sb = SerialBufferDevice {
+        rb: Queue::new()
+    };
+    let mut dev = SerialOutputDevice::new();
+    let mut cli : LightCliInput<U8> = LightCliInput::new();
+
+    // the path of the subcommand in the third line does not fit into the buffer
+    sb.write_str("HELLO Name=ABCDEFGHIJ\nEHLO\nNET ROUTE\nHELLO Name=\"A\\xZZ\"\n");
+    cli.fill(&mut sb).unwrap();
+
+    {
+        let mut cl_out = LightCliOutput::new(&mut dev);
+
+        lightcli!(cli, cl_out, cmd, key, val, [
+            "HELLO" => [ "Name" => () ] => writeln!(cl_out, "{}", cmd).unwrap();
+            "EHLO" => [] => writeln!(cl_out, "{}", cmd).unwrap();
+            "NET" => {
+                "ROUTE" => [] => writeln!(cl_out, "{}", cmd).unwrap()
+            }
+        ]);
+
+        cl_out.flush().unwrap();
+    }
+
+    let s = dev.read_str();
+    let expected = concat!(
+        "Line too long at column 20\n",
+        "EHLO\n",
+        "Line too long at column 5\n",
+        "Invalid escape sequence at column 16\n");
+    assert!(s == expected, "s={:?}", s);
+    assert!(cli.errors() == 3, "errors={}", cli.errors());
+
+    cli.reset_errors();
+    assert!(cli.errors() == 0);
+}
+
+#[test]
+pub fn test_invalid_utf8() {
+    let mut sb = SerialBufferDevice {
+        rb: Queue::new()
+    };
+    let mut cli : LightCliInput<U32> = LightCliInput::new();
+
+    // stray continuation byte, overlong encoding and an encoded surrogate
+    sb.write_str("HELLO Name=A");
+    sb.write(&[0x80, b'\n', 0xc0, 0xaf, b'\n', 0xed, 0xa0, 0x80, b'\n']);
+    sb.write_str("EHLO\n");
+    cli.fill(&mut sb).unwrap();
+
+    let mut errors = 0;
+    let mut columns = [0; 3];
+    let mut done = false;
+
+    loop {
+        let res = cli.parse_data(|cbcmd| {
+            match cbcmd {
+                CallbackCommand::Command(cmd) => {
+                    assert!(cmd == "EHLO", "cmd={}", cmd);
+                    done = true;
+                },
+                CallbackCommand::Error { kind: tokenizer::Error::InvalidUTF8, position, .. } => 
+                    columns[errors] = position,
+                _ => assert!(false, "Unexpected event."),
+            }
+        });
+
+        match res {
+            Err(nb::Error::Other(tokenizer::Error::InvalidUTF8)) => errors += 1,
+            Err(nb::Error::WouldBlock) => break,
+            _ => assert!(false, "Unexpected result."),
+        }
+    }
+
+    assert!(errors == 3, "errors={}", errors);
+    assert!(columns == [13, 1, 1], "columns={:?}", columns);
+    assert!(done);
+}
+
+#[test]
+pub fn test_encoding() {
+    let mut sb = SerialBufferDevice {
+        rb: Queue::new()
+    };
+    let mut cli : LightCliInput<U32> = LightCliInput::new();
+
+    cli.set_encoding(Encoding::Utf8Lossy);
+    sb.write_str("HELLO Name=A");
+    sb.write(&[0xc0, 0xaf, b'B', 0xe2, 0x82, b'\n']);
+    cli.fill(&mut sb).unwrap();
+
+    let mut ran = false;
+
+    let _ = cli.parse_data(|cbcmd| {
+        match cbcmd {
+            CallbackCommand::Attribute(_, _, val) => {
+                assert!(val == "A\u{fffd}\u{fffd}B\u{fffd}", "val={}", val);
+                ran = true;
+            },
+            CallbackCommand::Command(cmd) => assert!(cmd == "HELLO", "cmd={}", cmd),
+            _ => assert!(false, "Unexpected event."),
+        }
+    });
+
+    assert!(ran);
+
+    cli.set_encoding(Encoding::Latin1);
+    sb.write_str("HELLO Name=");
+    sb.write(&[b'A', 0xe9, 0x80, b'\n']);
+    cli.fill(&mut sb).unwrap();
+
+    ran = false;
+
+    let _ = cli.parse_data(|cbcmd| {
+        match cbcmd {
+            CallbackCommand::Attribute(_, _, val) => {
+                assert!(val == "A\u{e9}\u{80}", "val={}", val);
+                ran = true;
+            },
+            CallbackCommand::Command(cmd) => assert!(cmd == "HELLO", "cmd={}", cmd),
+            _ => assert!(false, "Unexpected event."),
+        }
+    });
+
+    assert!(ran);
+}
+
+#[test]
+pub fn test_buffer_full() {
+    let mut sb = SerialBufferDevice {
+        rb: Queue::new()
+    };
+    let mut cli : LightCliInput<U32, U8> = LightCliInput::new();
+
+    // a full buffer is fine as long as nothing has been dropped
+    sb.write_str("HELLO X=");
+    cli.fill(&mut sb).unwrap();
+    cli.fill(&mut sb).unwrap();
+
+    sb.write_str("1\nEHLO\n");
+
+    match cli.fill(&mut sb) {
+        Err(nb::Error::Other(FillError::BufferFull)) => (),
+        _ => assert!(false, "Expected the buffer to be full."),
+    }
+    assert!(cli.overruns() == 1, "overruns={}", cli.overruns());
+
+    let mut commands = 0;
+
+    while commands < 2 {
+        let _ = cli.parse_data(|cbcmd| {
+            match cbcmd {
+                CallbackCommand::Attribute(cmd, key, val) => {
+                    // the value has been dropped
+                    assert!(cmd == "HELLO" && key == "X" && val.is_empty(), "val={}", val);
+                },
+                CallbackCommand::Command(cmd) => {
+                    assert!(cmd == ["HELLO", "EHLO"][commands], "cmd={}", cmd);
+                    commands += 1;
+                },
+                _ => assert!(false, "Unexpected event."),
+            }
+        });
+        let _ = cli.fill(&mut sb);
+    }
+
+    assert!(cli.overruns() == 1, "overruns={}", cli.overruns());
+
+    cli.reset_overruns();
+    cli.set_overrun_policy(OverrunPolicy::DropOldest);
+    sb.write_str("GARBAGE\nEHLO\n");
+    cli.fill(&mut sb).unwrap();
+    assert!(cli.overruns() == 5, "overruns={}", cli.overruns());
+
+    cli.reset_overruns();
+    cli.set_overrun_policy(OverrunPolicy::DropNewest);
+    sb.write_str("X");
+    cli.fill(&mut sb).unwrap();
+    assert!(cli.overruns() == 1, "overruns={}", cli.overruns());
+
+    // the start of the first line was dropped
+    commands = 0;
+
+    let _ = cli.parse_data(|cbcmd| {
+        match cbcmd {
+            CallbackCommand::Command(cmd) => {
+                assert!(cmd == ["GE", "EHLO"][commands], "cmd={}", cmd);
+                commands += 1;
+            },
+            _ => assert!(false, "Unexpected event."),
+        }
+    });
+
+    assert!(commands == 2, "commands={}", commands);
+}
+
+#[test]
+pub fn test_output_buffer() {
+    let mut dev = SerialOutputDevice::new();
+    dev.blocked = true;
+
+    {
+        let mut cl_out : LightCliOutput<Error, U16> = LightCliOutput::new_sized(&mut dev);
+
+        assert!(cl_out.capacity() == 16);
+        assert!(cl_out.is_empty());
+
+        write!(cl_out, "EHLO Name=").unwrap();
+        assert!(cl_out.len() == 10, "len={}", cl_out.len());
+        assert!(cl_out.free() == 6, "free={}", cl_out.free());
+    }
+
+    dev.blocked = false;
+
+    let mut cl_out : LightCliOutput<Error, U16> = LightCliOutput::new_sized(&mut dev);
+    writeln!(cl_out, "A status message longer than the buffer").unwrap();
+    cl_out.flush().unwrap();
+    assert!(cl_out.is_empty());
+    assert!(cl_out.free() == 16);
+    drop(cl_out);
+
+    assert!(dev.read_str() == "A status message longer than the buffer\n");
+}
+
+#[test]
+pub fn test_output_non_blocking() {
+    let mut dev = SerialOutputDevice::new();
+    dev.blocked = true;
+
+    {
+        let mut cl_out : LightCliOutput<Error, U16> = LightCliOutput::new_sized(&mut dev);
+        cl_out.set_write_mode(WriteMode::NonBlocking);
+
+        // the heart is three bytes long and does not fit completely
+        write!(cl_out, "A message: ❤❤").unwrap();
+        assert!(cl_out.len() == 14, "len={}", cl_out.len());
+        assert!(cl_out.dropped() == 3, "dropped={}", cl_out.dropped());
+
+        cl_out.reset_dropped();
+        cl_out.set_write_mode(WriteMode::Blocking);
+
+        match cl_out.try_write_str("ABC") {
+            Err(nb::Error::WouldBlock) => (),
+            _ => assert!(false, "Expected the write to be rejected."),
+        }
+        assert!(cl_out.try_write_str("AB").is_ok());
+        assert!(cl_out.free() == 0);
+        assert!(cl_out.dropped() == 0);
+    }
+
+    dev.blocked = false;
+
+    let mut cl_out : LightCliOutput<Error, U16> = LightCliOutput::new_sized(&mut dev);
+    cl_out.try_write_fmt(format_args!("EHLO Name={}\n", "Foo")).unwrap();
+    assert!(cl_out.len() == 14, "len={}", cl_out.len());
+
+    match cl_out.try_write_fmt(format_args!("{}", "Too long")) {
+        Err(nb::Error::WouldBlock) => assert!(false, "Expected the buffer to be flushed."),
+        r => r.unwrap(),
+    }
+    cl_out.flush().unwrap();
+    drop(cl_out);
+
+    assert!(dev.read_str() == "EHLO Name=Foo\nToo long");
+}
+
+#[test]
+pub fn test_arguments() {
+    let mut sb = SerialBufferDevice {
+        rb: Queue::new()
+    };
+    let mut cli : LightCliInput<U32> = LightCliInput::new();
+
+    sb.write_str("LED 3 state=on \"a b\"\n");
+    cli.fill(&mut sb).unwrap();
+
+    let mut args = 0;
+    let mut ran = false;
+    let mut done = false;
+
+    let _ = cli.parse_data(|cbcmd| {
+        match cbcmd {
+            CallbackCommand::Argument(cmd, index, val) => {
+                assert!(cmd == "LED", "cmd={}", cmd);
+                assert!(index == args, "index={}", index);
+                assert!(val == ["3", "a b"][index], "val={}", val);
+                args += 1;
+            },
+            CallbackCommand::Attribute(cmd, key, val) => {
+                assert!(cmd == "LED", "cmd={}", cmd);
+                assert!(key == "state", "key={}", key);
+                assert!(val == "on", "val={}", val);
+                ran = true;
+            },
+            CallbackCommand::Command(cmd) => {
+                assert!(cmd == "LED", "cmd={}", cmd);
+                done = true;
+            },
+            _ => assert!(false, "Unexpected event."),
+        }
+    });
+
+    assert!(args == 2, "args={}", args);
+    assert!(ran);
+    assert!(done);
+}
+
+#[test]
+pub fn test_macro_arguments() {
+    let mut sb = SerialBufferDevice {
+        rb: Queue::new()
+    };
+    let mut dev = SerialOutputDevice::new();
+    let mut cli : LightCliInput<U32> = LightCliInput::new();
+    let mut cl_out = LightCliOutput::new(&mut dev);
+
+    sb.write_str("LED 3 state=on\nLED 1 2\n");
+    cli.fill(&mut sb).unwrap();
+
+    let mut led : u8 = 0;
+    let mut on = false;
+    let mut count = 0;
+
+    lightcli!(cli, cl_out, cmd, key, val, [
+        "LED" => [
+            [0] => led = val.parse().unwrap(),
+            "state" => on = val == "on"
+        ] => count += 1
+    ]);
+
+    assert!(led == 1, "led={}", led);
+    assert!(on);
+    assert!(count == 2, "count={}", count);
+
+    cl_out.flush().unwrap();
+    drop(cl_out);
+    assert!(dev.read_str() == "Unexpected argument for command LED: 2\n");
+}
+
+#[test]
+pub fn test_macro_flags() {
+    let mut sb = SerialBufferDevice {
+        rb: Queue::new()
+    };
+    let mut cli : LightCliInput<U32> = LightCliInput::new();
+
+    sb.write_str("RESET force\nRESET force \nRESET\nRESET 5 force\n");
+    // a flag in front of a positional argument does not take up its index
+    sb.write_str("RESET force 7\n");
+    cli.fill(&mut sb).unwrap();
+
+    let mut flags = 0;
+    let mut delay = 0;
+    let mut count = 0;
+
+    lightcli_adv!(cli, cmd, key, val, [
+            "RESET" => [
+                [0] => delay = val.parse().unwrap(),
+                "force" => {
+                    assert!(key == "force", "key={}", key);
+                    assert!(val.is_empty(), "val={}", val);
+                    flags += 1
+                }
+            ] => count += 1
+        ],
+        assert!(false, "Unknown cmd {}", cmd),
+        assert!(false, "Unknown key {} for cmd {}", key, cmd), 
+        assert!(false, "Unknown cmd done {}", cmd),
+        error => assert!(false, "{} at column {}", val, key),
+        unexpected => assert!(false, "Unexpected {} for cmd {}", val, cmd)
+    );
+
+    assert!(flags == 4, "flags={}", flags);
+    assert!(delay == 7, "delay={}", delay);
+    assert!(count == 5, "count={}", count);
+}
+
+#[test]
+pub fn test_line_editing() {
+    let mut sb = SerialBufferDevice {
+        rb: Queue::new()
+    };
+    let mut cli : LightCliInput<U32> = LightCliInput::new();
+    cli.set_interactive(true);
+
+    sb.write_str("HELLX\x08O Name=Fox\x7fo\r\n");
+    sb.write_str("garbage\x15EHLO\n");
+    sb.write_str("SET a=1 b\x17c=2\n");
+    sb.write_str("junk\x03SET v=❤\x7f!\n");
+    cli.fill(&mut sb).unwrap();
+
+    let mut attributes = 0;
+    let mut commands = 0;
+
+    let _ = cli.parse_data(|cbcmd| {
+        match cbcmd {
+            CallbackCommand::Attribute(cmd, key, val) => {
+                let expected = [("HELLO", "Name", "Foo"), ("SET", "a", "1"), ("SET", "c", "2"), ("SET", "v", "!")];
+                assert!((cmd, key, val) == expected[attributes], "cmd={} key={} val={}", cmd, key, val);
+                attributes += 1;
+            },
+            CallbackCommand::Command(cmd) => {
+                assert!(cmd == ["HELLO", "EHLO", "SET", "SET"][commands], "cmd={}", cmd);
+                commands += 1;
+            },
+            _ => assert!(false, "Unexpected event."),
+        }
+    });
+
+    assert!(attributes == 4, "attributes={}", attributes);
+    assert!(commands == 4, "commands={}", commands);
+
+    // incomplete lines are held back
+    sb.write_str("EHLO");
+    cli.fill(&mut sb).unwrap();
+    let _ = cli.parse_data(|_| assert!(false, "Unexpected event."));
+}
+
+#[test]
+pub fn test_line_longer_than_buffer() {
+    let mut sb = SerialBufferDevice {
+        rb: Queue::new()
+    };
+    // lines may be longer than the default buffer of 64 bytes
+    let mut cli : LightCliInput<U128> = LightCliInput::new();
+    cli.set_interactive(true);
+
+    sb.write_str("SET name=");
+    for _ in 0..69 {
+        sb.write_str("x");
+    }
+    sb.write_str("\r");
+    cli.fill(&mut sb).unwrap();
+
+    let mut attributes = 0;
+    let mut commands = 0;
+
+    let _ = cli.parse_data(|cbcmd| {
+        match cbcmd {
+            CallbackCommand::Attribute("SET", "name", val) => {
+                // only as much of the line as fits into the buffer is accepted
+                assert!(val.len() == 63 - 9, "len={}", val.len());
+                attributes += 1;
+            },
+            CallbackCommand::Command("SET") => commands += 1,
+            _ => assert!(false, "Unexpected event."),
+        }
+    });
+
+    assert!(attributes == 1, "attributes={}", attributes);
+    assert!(commands == 1, "commands={}", commands);
+    assert!(cli.overruns() == 0, "overruns={}", cli.overruns());
+}
+
+#[test]
+pub fn test_echo() {
+    let mut sb = SerialBufferDevice {
+        rb: Queue::new()
+    };
+    let mut dev = SerialOutputDevice::new();
+    let mut cli : LightCliInput<U32> = LightCliInput::new();
+    cli.set_interactive(true);
+
+    {
+        let mut cl_out = LightCliOutput::new(&mut dev);
+
+        // echo is disabled by default
+        sb.write_str("EHLO\n");
+        cli.fill_echo(&mut sb, &mut cl_out).unwrap();
+        assert!(cl_out.is_empty());
+
+        cli.set_echo(true);
+        sb.write_str("HELP\x08LO a bc\x17\x7f\r\njunk\x03");
+        cli.fill_echo(&mut sb, &mut cl_out).unwrap();
+        cl_out.flush().unwrap();
+    }
+
+    let s = dev.read_str();
+    assert!(s == "HELP\x08 \x08LO a bc\x08 \x08\x08 \x08\x08 \x08\r\njunk^C\r\n", "s={:?}", s);
+}
+
+#[test]
+pub fn test_history() {
+    let mut sb = SerialBufferDevice {
+        rb: Queue::new()
+    };
+    let mut dev = SerialOutputDevice::new();
+    let mut cli : LightCliInput<U32, U64, U2> = LightCliInput::new();
+    cli.set_interactive(true);
+
+    // empty and repeated lines are not added, the oldest line is replaced
+    sb.write_str("HELLO\n\nEHLO\nEHLO\nSET a=1\n");
+    cli.fill(&mut sb).unwrap();
+    let _ = cli.parse_data(|_| ());
+
+    {
+        let mut history = cli.history();
+        assert!(history.next() == Some(&b"EHLO"[..]));
+        assert!(history.next() == Some(&b"SET a=1"[..]));
+        assert!(history.next().is_none());
+    }
+
+    cli.set_echo(true);
+
+    {
+        let mut cl_out = LightCliOutput::new(&mut dev);
+
+        // go back past the oldest line and then one forward again
+        sb.write_str("x\x1b[A\x1b[A\x1b[A\x1bOB2\r\n");
+        cli.fill_echo(&mut sb, &mut cl_out).unwrap();
+        cl_out.flush().unwrap();
+    }
+
+    let s = dev.read_str();
+    assert!(s == "x\x1b[1D\x1b[KSET a=1\x1b[7D\x1b[KEHLO\x1b[4D\x1b[KSET a=12\r\n", "s={:?}", s);
+
+    let mut attributes = 0;
+
+    let _ = cli.parse_data(|cbcmd| {
+        match cbcmd {
+            CallbackCommand::Attribute(cmd, key, val) => {
+                assert!(cmd == "SET" && key == "a" && val == "12", "cmd={} key={} val={}", cmd, key, val);
+                attributes += 1;
+            },
+            CallbackCommand::Command(cmd) => assert!(cmd == "SET"),
+            _ => assert!(false, "Unexpected event."),
+        }
+    });
+
+    assert!(attributes == 1);
+}
+
+#[test]
+pub fn test_completion() {
+    let mut sb = SerialBufferDevice {
+        rb: Queue::new()
+    };
+    let mut dev = SerialOutputDevice::new();
+    let mut cli : LightCliInput<U32> = LightCliInput::new();
+    cli.set_interactive(true);
+    cli.set_echo(true);
+
+    let mut name : String<U32> = String::new();
+    let mut commands = 0;
+
+    for line in ["", "EH\t\n\x15HE\tLO N\t=x Y\t\nNET I\tSH\t\n"].iter() {
+        {
+            let mut cl_out = LightCliOutput::new(&mut dev);
+            sb.write_str(line);
+            cli.fill_echo(&mut sb, &mut cl_out).unwrap();
+            cl_out.flush().unwrap();
+        }
+
+        // the first call registers the commands
+        lightcli_adv!(cli, cmd, key, val, [
+                "HELLO" => [
+                    "Name" => name = String::from(val),
+                    [0] => ()
+                ] => commands += 1;
+                "HELP" => [] => assert!(false, "HELP");
+                "EHLO" => [] => commands += 1;
+                // subcommands are completed after their group
+                "NET" => {
+                    "IP" => {
+                        "SHOW" => [] => commands += 1
+                    }
+                }
+            ],
+            assert!(false, "Unknown cmd {}", cmd),
+            assert!(false, "Unknown key {} for cmd {}", key, cmd), 
+            assert!(false, "Unknown cmd done {}", cmd)
+        );
+    }
+
+    let s = dev.read_str();
+    assert!(s == "EHLO \r\nHE\r\nHELLO  HELP\r\nHELLO Name=x Y\r\nNET IP SHOW \r\n", "s={:?}", s);
+    assert!(commands == 3, "commands={}", commands);
+    assert!(name == "x", "name={}", name);
+}
+
+#[test]
+pub fn test_completion_ignore_case() {
+    let mut sb = SerialBufferDevice {
+        rb: Queue::new()
+    };
+    let mut dev = SerialOutputDevice::new();
+    let mut cli : LightCliInput<U32> = LightCliInput::new();
+    cli.set_interactive(true);
+    cli.set_echo(true);
+    cli.set_ignore_case(true);
+
+    let mut commands = 0;
+
+    for line in ["", "ehl\t\nnet i\ts\t\n"].iter() {
+        {
+            let mut cl_out = LightCliOutput::new(&mut dev);
+            sb.write_str(line);
+            cli.fill_echo(&mut sb, &mut cl_out).unwrap();
+            cl_out.flush().unwrap();
+        }
+
+        // names are completed as spelled in the table
+        lightcli_adv!(cli, cmd, key, val, [
+                "EHLO" => [] => commands += 1;
+                "NET" => {
+                    "IP" => {
+                        "SHOW" => [] => commands += 1
+                    }
+                }
+            ],
+            assert!(false, "Unknown cmd {}", cmd),
+            assert!(false, "Unknown key {} for cmd {}", key, cmd), 
+            assert!(false, "Unknown cmd done {}", cmd)
+        );
+    }
+
+    let s = dev.read_str();
+    assert!(s == "ehlO \r\nnet iP sHOW \r\n", "s={:?}", s);
+    assert!(commands == 2, "commands={}", commands);
+}
+
+#[test]
+pub fn test_cursor() {
+    let mut sb = SerialBufferDevice {
+        rb: Queue::new()
+    };
+    let mut dev = SerialOutputDevice::new();
+    let mut cli : LightCliInput<U32> = LightCliInput::new();
+    cli.set_interactive(true);
+    cli.set_echo(true);
+
+    sb.write_str("HELLO Nme=Bobb\x1b[D\x1b[D\x1b[D\x1b[D\x1b[D\x1b[D\x1b[D");
+    sb.write_str("ax\x7f\x05\x1bOD\x1b[3~\x1b[1~\r");
+    sb.write_str("SET v=ab\x1b[D❤\x01\x1b[C\x1b[1;5C\x08\n");
+
+    {
+        let mut cl_out = LightCliOutput::new(&mut dev);
+        cli.fill_echo(&mut sb, &mut cl_out).unwrap();
+        cl_out.flush().unwrap();
+    }
+
+    let s = dev.read_str();
+    let expected = concat!(
+        "HELLO Nme=Bobb\x1b[1D\x1b[1D\x1b[1D\x1b[1D\x1b[1D\x1b[1D\x1b[1D",
+        "ame=Bobb\x1b[K\x1b[7D", "xme=Bobb\x1b[K\x1b[7D", "\x1b[1Dme=Bobb\x1b[K\x1b[7D", 
+        "\x1b[7C", "\x1b[1D", "\x1b[K", "\x1b[14D\r\n",
+        // the heart is printed once it is complete, in ISO 8859-1 as read back
+        "SET v=ab\x1b[1D\u{e2}\u{9d}\u{a4}b\x1b[K\x1b[1D", "\x1b[8D\x1b[1C\x1b[1C", 
+        "\x1b[1DT v=a\u{e2}\u{9d}\u{a4}b\x1b[K\x1b[7D\r\n");
+    assert!(s == expected, "s={:?}", s);
+
+    let mut attributes = 0;
+
+    let _ = cli.parse_data(|cbcmd| {
+        match cbcmd {
+            CallbackCommand::Attribute(cmd, key, val) => {
+                let expected = [("HELLO", "Name", "Bob"), ("ST", "v", "a❤b")];
+                assert!((cmd, key, val) == expected[attributes], "cmd={} key={} val={}", cmd, key, val);
+                attributes += 1;
+            },
+            CallbackCommand::Command(_) => (),
+            _ => assert!(false, "Unexpected event."),
+        }
+    });
+
+    assert!(attributes == 2, "attributes={}", attributes);
+}
+
+#[test]
+pub fn test_prompt() {
+    let mut sb = SerialBufferDevice {
+        rb: Queue::new()
+    };
+    let mut dev = SerialOutputDevice::new();
+    let mut cli : LightCliInput<U32> = LightCliInput::new();
+
+    let mode = Cell::new("idle");
+    let prompt = |w: &mut dyn Write| write!(w, "{}> ", mode.get());
+
+    {
+        let mut cl_out = LightCliOutput::new(&mut dev);
+        cl_out.set_prompt(Prompt::Dynamic(&prompt));
+
+        // the prompt is printed on startup and once all commands have been handled
+        for line in ["", "RUN\nEHLO\nFOO\n", "", "EHLO\n"].iter() {
+            sb.write_str(line);
+            cli.fill(&mut sb).unwrap();
+
+            lightcli!(cli, cl_out, cmd, key, val, [
+                "RUN" => [] => mode.set("run");
+                "EHLO" => [] => writeln!(cl_out, "EHLO").unwrap()
+            ]);
+        }
+
+        cl_out.flush().unwrap();
+    }
+
+    let s = dev.read_str();
+    assert!(s == "idle> EHLO\nUnknown command: FOO\nrun> EHLO\nrun> ", "s={:?}", s);
+
+    cli.set_interactive(true);
+    cli.set_echo(true);
+
+    {
+        let mut cl_out = LightCliOutput::new(&mut dev);
+        cl_out.set_prompt(Prompt::Static("> "));
+        cl_out.show_prompt().unwrap();
+
+        // empty and aborted lines only print the prompt again
+        sb.write_str("\r\nab\x03");
+        cli.fill_echo(&mut sb, &mut cl_out).unwrap();
+        cl_out.flush().unwrap();
+    }
+
+    let s = dev.read_str();
+    assert!(s == "> \r\n> ab^C\r\n> ", "s={:?}", s);
+}
+
+#[test]
+pub fn test_help() {
+    let mut sb = SerialBufferDevice {
+        rb: Queue::new()
+    };
+    let mut dev = SerialOutputDevice::new();
+    let mut cli : LightCliInput<U32> = LightCliInput::new();
+
+    sb.write_str("HELP\nHELP HELLO\nHELP FOO\n");
+    cli.fill(&mut sb).unwrap();
+
+    {
+        let mut cl_out = LightCliOutput::new(&mut dev);
+
+        lightcli!(cli, cl_out, cmd, key, val, [
+            /// Set the name
+            "HELLO" => [
+                /// The name to use
+                "Name" => (),
+                /// The greeting
+                [0] => ()
+            ] => {};
+            "EHLO" => [] => {}
+        ]);
+
+        cl_out.flush().unwrap();
+    }
+
+    let s = dev.read_str();
+    let expected = concat!(
+        "Commands:\n", 
+        "  HELLO  Set the name\n", 
+        "  EHLO\n", 
+        "  HELP   Show the available commands or describe a single command.\n",
+        "HELLO: Set the name\n",
+        "  [0]   The greeting\n",
+        "  Name  The name to use\n",
+        "Unknown command: FOO\n");
+    assert!(s == expected, "s={:?}", s);
+
+    {
+        let mut cl_out = LightCliOutput::new(&mut dev);
+
+        // the command given to HELP is kept if the line ends in a later call
+        for line in ["HELP HELLO ", "\n"].iter() {
+            sb.write_str(line);
+            cli.fill(&mut sb).unwrap();
+
+            lightcli!(cli, cl_out, cmd, key, val, [
+                /// Set the name
+                "HELLO" => [] => {}
+            ]);
+        }
+
+        cl_out.flush().unwrap();
+    }
+
+    let s = dev.read_str();
+    assert!(s == "HELLO: Set the name\n", "s={:?}", s);
+}
+
+#[test]
+pub fn test_ignore_case() {
+    let mut sb = SerialBufferDevice {
+        rb: Queue::new()
+    };
+    let mut dev = SerialOutputDevice::new();
+    let mut cli : LightCliInput<U32> = LightCliInput::new();
+
+    sb.write_str("hello name=foo\nHeLLo NAME=Bar Force\nnet ip show\nbye\n");
+    cli.fill(&mut sb).unwrap();
+    cli.set_ignore_case(true);
+
+    let mut name : String<U32> = String::new();
+    let mut forced = false;
+
+    {
+        let mut cl_out = LightCliOutput::new(&mut dev);
+
+        lightcli!(cli, cl_out, cmd, key, val, [
+            "HELLO" => [
+                "Name" => name = String::from(val),
+                "force" => forced = true
+            ] => writeln!(cl_out, "{} {}", cmd, name).unwrap();
+            "NET" => {
+                "IP" => {
+                    "SHOW" => [] => writeln!(cl_out, "{}", cmd).unwrap()
+                }
+            }
+        ]);
+
+        cl_out.flush().unwrap();
+    }
+
+    let s = dev.read_str();
+    let expected = concat!(
+        "HELLO foo\n",
+        "HELLO Bar\n",
+        "NET IP SHOW\n",
+        "Unknown command: bye\n");
+    assert!(s == expected, "s={:?}", s);
+    assert!(forced);
+}
+
+#[test]
+pub fn test_abbreviations() {
+    let mut sb = SerialBufferDevice {
+        rb: Queue::new()
+    };
+    let mut dev = SerialOutputDevice::new();
+    let mut cli : LightCliInput<U32, U256> = LightCliInput::new();
+
+    sb.write_str("HELL Na=Foo\nHE N=x\nHELLO N=1 Name=y\nHELLO Nu=5 Na=Bar\n");
+    sb.write_str("NET IP SH\nNET I SHOW\nNET IPV6\nhell na=baz\n");
+    cli.fill(&mut sb).unwrap();
+    cli.set_abbreviations(true);
+
+    let mut name : String<U32> = String::new();
+    let mut number = 0;
+
+    {
+        let mut cl_out = LightCliOutput::new(&mut dev);
+
+        lightcli!(cli, cl_out, cmd, key, val, [
+            "HELLO" => [
+                "Name" => name = String::from(val),
+                "Number": u8 => number = val
+            ] => writeln!(cl_out, "{} {} {}", cmd, name, number).unwrap();
+            "NET" => {
+                "IP" => {
+                    "SHOW" => [] => writeln!(cl_out, "{}", cmd).unwrap()
+                };
+                "IPV6" => [] => writeln!(cl_out, "{}", cmd).unwrap()
+            }
+        ]);
+
+        cli.set_ignore_case(true);
+        cl_out.flush().unwrap();
+    }
+
+    let s = dev.read_str();
+    let expected = concat!(
+        "HELLO Foo 0\n",
+        "Ambiguous command: HE (HELLO, HELP)\n",
+        "Ambiguous key for command HELLO: N (Name, Number)\n",
+        "HELLO Bar 5\n",
+        "NET IP SHOW\n",
+        "Ambiguous subcommand for command NET: I (IP, IPV6)\n",
+        "NET IPV6\n",
+        "Unknown command: hell\n");
+    assert!(s == expected, "s={:?}", s);
+
+    {
+        let mut cl_out = LightCliOutput::new(&mut dev);
+        // HELP matches the command it describes like the commands on the line
+        sb.write_str("hell na=baz\nhelp hell\n");
+        cli.fill(&mut sb).unwrap();
+
+        lightcli!(cli, cl_out, cmd, key, val, [
+            "HELLO" => [
+                "Name" => name = String::from(val)
+            ] => writeln!(cl_out, "{} {}", cmd, name).unwrap()
+        ]);
+
+        cl_out.flush().unwrap();
+    }
+
+    let s = dev.read_str();
+    assert!(s == "HELLO baz\nHELLO\n  Name\n", "s={:?}", s);
+}
+
+#[test]
+pub fn test_subcommands() {
+    static SET_KEYS: [KeyInfo; 1] = [
+        KeyInfo { name: "addr", description: "", required: true },
+    ];
+    static IP: [CommandInfo; 2] = [
+        CommandInfo { name: "SET", description: "", keys: &SET_KEYS, arguments: &[], subcommands: &[] },
+        CommandInfo { name: "SHOW", description: "", keys: &[], arguments: &[], subcommands: &[] },
+    ];
+    static NET: [CommandInfo; 1] = [
+        CommandInfo { name: "IP", description: "", keys: &[], arguments: &[], subcommands: &IP },
+    ];
+    static SENSOR_ARGS: [KeyInfo; 1] = [
+        KeyInfo { name: "[0]", description: "", required: true },
+    ];
+    static SENSOR: [CommandInfo; 1] = [
+        CommandInfo { name: "CAL", description: "", keys: &[], arguments: &[], subcommands: &[] },
+    ];
+    static COMMANDS: [CommandInfo; 2] = [
+        CommandInfo { name: "NET", description: "", keys: &[], arguments: &[], subcommands: &NET },
+        CommandInfo { name: "SENSOR", description: "", keys: &[], arguments: &SENSOR_ARGS, subcommands: &SENSOR },
+    ];
+
+    let mut sb = SerialBufferDevice {
+        rb: Queue::new()
+    };
+    let mut cli : LightCliInput<U32, U256> = LightCliInput::new();
+
+    cli.set_commands(&COMMANDS);
+    sb.write_str("NET IP SET addr=10.0.0.1\n");
+    sb.write_str("NET IP SET\n");
+    sb.write_str("SENSOR 3 CAL\n");
+    sb.write_str("SENSOR CAL\n");
+    // a key ends the path of the subcommand
+    sb.write_str("NET addr=1 IP\n");
+    cli.fill(&mut sb).unwrap();
+
+    let mut events = 0;
+
+    let _ = cli.parse_data(|cbcmd| {
+        match (events, cbcmd) {
+            (0, CallbackCommand::Attribute("NET IP SET", "addr", "10.0.0.1")) => (),
+            (1, CallbackCommand::Command("NET IP SET")) => (),
+            (2, CallbackCommand::MissingKey("NET IP SET", "addr")) => (),
+            (3, CallbackCommand::Argument("SENSOR", 0, "3")) => (),
+            (4, CallbackCommand::Command("SENSOR CAL")) => (),
+            // the keys of a group are checked once its subcommand is entered
+            (5, CallbackCommand::MissingKey("SENSOR", "[0]")) => (),
+            (6, CallbackCommand::Attribute("NET", "addr", "1")) => (),
+            (7, CallbackCommand::Argument("NET", 0, "IP")) => (),
+            (8, CallbackCommand::Command("NET")) => (),
+            (_, event) => assert!(false, "Unexpected event {}: {}", events, event.message()),
+        }
+        events += 1;
+    });
+
+    assert!(events == 9, "events={}", events);
+}
+
+#[test]
+pub fn test_macro_subcommands() {
+    let mut sb = SerialBufferDevice {
+        rb: Queue::new()
+    };
+    let mut dev = SerialOutputDevice::new();
+    let mut cli : LightCliInput<U32, U256> = LightCliInput::new();
+
+    sb.write_str("NET IP SET addr=10.0.0.1\nNET IP SHOW\nSENSOR 3 CAL offset=-5\nSENSOR CAL offset=1\n");
+    sb.write_str("NET FOO\nNET\n");
+    sb.write_str("NET IP SET\nHELP NET IP\nHELP NET BAR\n");
+    cli.fill(&mut sb).unwrap();
+
+    let mut addr : String<U32> = String::new();
+    let mut sensor = 0;
+    let mut offset = 0;
+
+    {
+        let mut cl_out = LightCliOutput::new(&mut dev);
+
+        lightcli!(cli, cl_out, cmd, key, val, [
+            /// Network settings
+            "NET" => {
+                /// IP configuration
+                "IP" => {
+                    /// Set the address
+                    "SET" => [ #[required] "addr" => addr = String::from(val) ] => 
+                        writeln!(cl_out, "{} {}", cmd, addr).unwrap();
+                    /// Show the address
+                    "SHOW" => [] => writeln!(cl_out, "{}", cmd).unwrap()
+                }
+            };
+            "SENSOR" => [ #[required] [0]: u8 => sensor = val ] {
+                "CAL" => [ "offset": i16 => offset = val ] => 
+                    writeln!(cl_out, "{} {} {}", cmd, sensor, offset).unwrap()
+            }
+        ]);
+
+        cl_out.flush().unwrap();
+    }
+
+    let s = dev.read_str();
+    let expected = concat!(
+        "NET IP SET 10.0.0.1\n",
+        "NET IP SHOW\n",
+        "SENSOR CAL 3 -5\n",
+        "Missing key for command SENSOR: [0]\n",
+        "Unknown subcommand for command NET: FOO\n",
+        "NET: Network settings\n",
+        "Subcommands:\n",
+        "  IP  IP configuration\n",
+        "Missing key for command NET IP SET: addr\n",
+        "IP: IP configuration\n",
+        "Subcommands:\n",
+        "  SET   Set the address\n",
+        "  SHOW  Show the address\n",
+        "Unknown subcommand for command NET: BAR\n");
+    assert!(s == expected, "s={:?}", s);
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Mode {
+    Fast,
+    Slow,
+}
+
+impl ParseValue for Mode {
+    fn parse_value(s: &str) -> Option<Self> {
+        match s {
+            "fast" => Some(Mode::Fast),
+            "slow" => Some(Mode::Slow),
+            _ => None,
+        }
+    }
+}
+
+#[test]
+pub fn test_typed_values() {
+    let mut sb = SerialBufferDevice {
+        rb: Queue::new()
+    };
+    let mut dev = SerialOutputDevice::new();
+    let mut cli : LightCliInput<U32, U256> = LightCliInput::new();
+
+    sb.write_str("SET 0x0f Speed=0b101 Offset=-0x80 Gain=1.5 Enable=off Mode=slow\n");
+    sb.write_str("SET Speed=70000 Offset=-129 Gain=x Enable=maybe Mode=medium 256\n");
+    sb.write_str("SET Enable\n");
+    cli.fill(&mut sb).unwrap();
+
+    let mut channel = 0;
+    let mut speed = 0;
+    let mut offset = 0;
+    let mut gain = 0.0;
+    let mut enable = true;
+    let mut mode = Mode::Fast;
+    let mut count = 0;
+
+    {
+        let mut cl_out = LightCliOutput::new(&mut dev);
+
+        lightcli!(cli, cl_out, cmd, key, v, [
+            "SET" => [
+                [0]: u8 => channel = v,
+                "Speed": u16 => speed = v,
+                "Offset": i8 => offset = v,
+                "Gain": f32 => gain = v,
+                "Enable": bool => enable = v,
+                "Mode": Mode => mode = v
+            ] => count += 1
+        ]);
+
+        cl_out.flush().unwrap();
+    }
+
+    assert!(channel == 15, "channel={}", channel);
+    assert!(speed == 5, "speed={}", speed);
+    assert!(offset == -128, "offset={}", offset);
+    assert!(gain == 1.5, "gain={}", gain);
+    assert!(enable, "enable={}", enable);
+    assert!(mode == Mode::Slow, "mode={:?}", mode);
+    // the line with invalid values is not executed
+    assert!(count == 2, "count={}", count);
+
+    let s = dev.read_str();
+    let expected = concat!(
+        "Invalid value for key Speed: 70000\n",
+        "Invalid value for key Offset: -129\n",
+        "Invalid value for key Gain: x\n",
+        "Invalid value for key Enable: maybe\n",
+        "Invalid value for key Mode: medium\n",
+        "Invalid value for key [0]: 256\n");
+    assert!(s == expected, "s={:?}", s);
+
+    assert!(u32::parse_value("+0XfF") == Some(255));
+    assert!(u32::parse_value("-1").is_none());
+    assert!(u32::parse_value("0x-1").is_none());
+    assert!(i64::parse_value("-0x8000000000000000") == Some(i64::MIN));
+    assert!(bool::parse_value("ON") == Some(true));
+}
+
+#[test]
+pub fn test_constraints() {
+    let mut sb = SerialBufferDevice {
+        rb: Queue::new()
+    };
+    let mut dev = SerialOutputDevice::new();
+    let mut cli : LightCliInput<U32, U256> = LightCliInput::new();
+
+    sb.write_str("PWM 3 duty=50 mode=fast Name=Motor Label=Fan\n");
+    sb.write_str("PWM 4 duty=150 mode=medium Name=VeryLongName Label=Pump\n");
+    sb.write_str("PWM duty=-1\n");
+    cli.fill(&mut sb).unwrap();
+
+    let mut channel = 0;
+    let mut duty = 0;
+    let mut mode = "";
+    let mut name = String::<U8>::new();
+    let mut label = String::<U8>::new();
+    let mut count = 0;
+
+    {
+        let mut cl_out = LightCliOutput::new(&mut dev);
+
+        lightcli!(cli, cl_out, cmd, key, v, [
+            "PWM" => [
+                [0]: u8 where 0..4 => channel = v,
+                "duty": u8 where 0..=100 => duty = v,
+                "mode" where ["fast", "slow"] => mode = if v == "fast" { "fast" } else { "slow" },
+                "Name" where MaxLen(8) => name = String::from(v),
+                "Label": String<U8> where MaxLen(3) => label = v
+            ] => count += 1
+        ]);
+
+        cl_out.flush().unwrap();
+    }
+
+    assert!(channel == 3, "channel={}", channel);
+    assert!(duty == 50, "duty={}", duty);
+    assert!(mode == "fast", "mode={}", mode);
+    assert!(name.as_str() == "Motor", "name={}", name.as_str());
+    assert!(label.as_str() == "Fan", "label={}", label.as_str());
+    // only the first line is executed
+    assert!(count == 1, "count={}", count);
+
+    let s = dev.read_str();
+    let expected = concat!(
+        "[0] out of range 0..4\n",
+        "duty out of range 0..=100\n",
+        "mode not one of fast, slow\n",
+        "Name longer than 8 characters\n",
+        "Label longer than 3 characters\n",
+        "Invalid value for key duty: -1\n");
+    assert!(s == expected, "s={:?}", s);
+}
+
+#[test]
+pub fn test_required_keys() {
+    let mut sb = SerialBufferDevice {
+        rb: Queue::new()
+    };
+    let mut dev = SerialOutputDevice::new();
+    let mut cli : LightCliInput<U32, U256> = LightCliInput::new();
+
+    sb.write_str("SETIP addr=10.0.0.1 mask=255.0.0.0\n");
+    sb.write_str("SETIP addr=10.0.0.2\n");
+    sb.write_str("SETIP addr=10.0.0.3 mask=0 addr=10.0.0.4\n");
+    sb.write_str("SETIP mask=0 gateway gateway\n");
+    sb.write_str("HELP SETIP\n");
+    cli.fill(&mut sb).unwrap();
+
+    let mut addr = String::<U16>::new();
+    let mut count = 0;
+
+    {
+        let mut cl_out = LightCliOutput::new(&mut dev);
+
+        lightcli!(cli, cl_out, cmd, key, v, [
+            "SETIP" => [
+                /// The address
+                #[required]
+                "addr" => addr = String::from(v),
+                #[required]
+                "mask" => {},
+                "gateway" => {}
+            ] => count += 1
+        ]);
+
+        cl_out.flush().unwrap();
+    }
+
+    assert!(addr.as_str() == "10.0.0.3", "addr={}", addr.as_str());
+    assert!(count == 1, "count={}", count);
+
+    let s = dev.read_str();
+    let expected = concat!(
+        "Missing key for command SETIP: mask\n",
+        "Repeated key for command SETIP: addr\n",
+        "Repeated key for command SETIP: gateway\n",
+        "Missing key for command SETIP: addr\n",
+        "SETIP\n",
+        "  addr     The address (required)\n",
+        "  mask     (required)\n",
+        "  gateway\n");
+    assert!(s == expected, "s={:?}", s);
+}
+
+#[test]
+pub fn test_parsed_commands() {
+    static KEYS: [KeyInfo; 2] = [
+        KeyInfo { name: "addr", description: "", required: true },
+        KeyInfo { name: "force", description: "", required: false },
+    ];
+    static COMMANDS: [CommandInfo; 1] = [
+        CommandInfo { name: "SETIP", description: "", keys: &KEYS, arguments: &[], subcommands: &[] },
+    ];
+
+    let mut sb = SerialBufferDevice {
+        rb: Queue::new()
+    };
+    let mut cli : LightCliInput<U32, U256> = LightCliInput::new();
+    let mut command : ParsedCommand<U32, U3> = ParsedCommand::new();
+
+    cli.set_commands(&COMMANDS);
+    sb.write_str("SETIP addr=10.0.0.1 ");
+    cli.fill(&mut sb).unwrap();
+
+    let mut count = 0;
+    let mut errors = 0;
+
+    let _ = cli.parse_commands(&mut command, |_| count += 1);
+    assert!(count == 0, "count={}", count);
+
+    sb.write_str("eth0 force\n");
+    sb.write_str("SETIP addr=10.0.0.2 addr=10.0.0.3\n");
+    sb.write_str("SETIP eth0\n");
+    sb.write_str("SETIP addr=10.0.0.4 a b c\n");
+    sb.write_str("SETIP addr=10.0.0.5 \"\"=x\n");
+    sb.write_str("EHLO\n");
+    cli.fill(&mut sb).unwrap();
+
+    let _ = cli.parse_commands(&mut command, |res| {
+        match res {
+            Ok(cmd) => {
+                match count {
+                    0 => {
+                        assert!(cmd.name.as_str() == "SETIP", "name={}", cmd.name.as_str());
+                        assert!(cmd.get("addr") == Some("10.0.0.1"));
+                        assert!(cmd.get("mask").is_none());
+                        assert!(cmd.argument(0) == Some("eth0"));
+                        assert!(cmd.has_flag("force"));
+                        assert!(cmd.args.len() == 3, "len={}", cmd.args.len());
+                    },
+                    1 => {
+                        // a quoted empty key is still a key
+                        assert!(cmd.get("") == Some("x"));
+                        assert!(cmd.argument(0).is_none());
+                        assert!(cmd.args[1].key() == Some(""));
+                    },
+                    2 => {
+                        assert!(cmd.name.as_str() == "EHLO", "name={}", cmd.name.as_str());
+                        assert!(cmd.args.is_empty());
+                    },
+                    _ => assert!(false, "Unexpected command."),
+                }
+                count += 1;
+            },
+            Err(err) => {
+                match (errors, err) {
+                    (0, CallbackCommand::RepeatedKey("SETIP", "addr")) => (),
+                    (1, CallbackCommand::MissingKey("SETIP", "addr")) => (),
+                    (2, CallbackCommand::TooManyArguments("SETIP")) => (),
+                    _ => assert!(false, "Unexpected event."),
+                }
+                errors += 1;
+            },
+        }
+    });
+
+    assert!(count == 3, "count={}", count);
+    assert!(errors == 3, "errors={}", errors);
+}
+
+struct PwmCommand {
+    channel: u8,
+    duty: u8,
+    next_channel: Option<u8>,
+    next_duty: Option<u8>,
+    aborted: usize,
+}
+
+impl<E, OLEN> Command<E, OLEN> for PwmCommand where OLEN: ArrayLength<u8> {
+    fn name(&self) -> &'static str {
+        "PWM"
+    }
+
+    fn keys(&self) -> &'static [KeyInfo] {
+        static KEYS: [KeyInfo; 1] = [KeyInfo { name: "duty", description: "", required: true }];
+        &KEYS
+    }
+
+    fn on_attribute(&mut self, key: &str, val: &str) -> Result<(), KeyError> {
+        match key {
+            "duty" => {
+                self.next_duty = Some(u8::parse_value(val).ok_or(KeyError::Invalid)?);
+                Ok(())
+            },
+            _ => Err(KeyError::Unknown),
+        }
+    }
+
+    fn on_argument(&mut self, index: usize, word: &str) -> Result<(), KeyError> {
+        match index {
+            0 => {
+                self.next_channel = Some(u8::parse_value(word).ok_or(KeyError::Invalid)?);
+                Ok(())
+            },
+            _ => Err(KeyError::Unknown),
+        }
+    }
+
+    fn on_complete(&mut self, out: &mut LightCliOutput<E, OLEN>) {
+        self.channel = self.next_channel.take().unwrap_or(self.channel);
+        self.duty = self.next_duty.take().unwrap_or(self.duty);
+        writeln!(out, "PWM {} duty {}", self.channel, self.duty).unwrap();
+    }
+
+    fn on_abort(&mut self) {
+        self.next_channel = None;
+        self.next_duty = None;
+        self.aborted += 1;
+    }
+}
+
+#[test]
+pub fn test_dispatcher() {
+    let mut sb = SerialBufferDevice {
+        rb: Queue::new()
+    };
+    let mut dev = SerialOutputDevice::new();
+    let mut table = [CommandInfo::default(); 1];
+    let mut cli : LightCliInput<U32, U256> = LightCliInput::new();
+    let mut pwm = PwmCommand { channel: 0, duty: 0, next_channel: None, next_duty: None, aborted: 0 };
+
+    sb.write_str("PWM 2 duty=50\n");
+    sb.write_str("PWM duty=x 1 2\n");
+    sb.write_str("PWM speed=1 duty=1 duty=2\n");
+    sb.write_str("PWM 3\n");
+    // unknown keys are reported, but the command is still completed
+    sb.write_str("PWM 4 duty=60 x=5\n");
+    sb.write_str("I2C addr=1\n");
+    sb.write_str("pwm DUTY=75\n");
+    cli.set_ignore_case(true);
+    cli.fill(&mut sb).unwrap();
+
+    {
+        let mut cl_out = LightCliOutput::new(&mut dev);
+        cl_out.set_prompt(Prompt::Static("> "));
+
+        let mut commands : [&mut dyn Command<Error>; 1] = [&mut pwm];
+        let mut dispatcher = Dispatcher::new(&mut commands, &mut table);
+        let _ = dispatcher.dispatch(&mut cli, &mut cl_out);
+
+        cl_out.flush().unwrap();
+    }
+
+    // none of the values of the failed lines have been applied
+    assert!(pwm.channel == 4, "channel={}", pwm.channel);
+    assert!(pwm.duty == 75, "duty={}", pwm.duty);
+    assert!(pwm.aborted == 3, "aborted={}", pwm.aborted);
+
+    let s = dev.read_str();
+    let expected = concat!(
+        "PWM 2 duty 50\n",
+        "Invalid value for key duty: x\n",
+        "Unexpected argument for command PWM: 2\n",
+        "Unknown key for command PWM: speed\n",
+        "Repeated key for command PWM: duty\n",
+        "Missing key for command PWM: duty\n",
+        "Unknown key for command PWM: x\n",
+        "PWM 4 duty 60\n",
+        "Unknown command: I2C\n",
+        "PWM 4 duty 75\n",
+        "> ");
+    assert!(s == expected, "s={:?}", s);
+}
@@ -1,23 +1,104 @@
 use core;
+use core::fmt;
 
 use nb;
 use heapless;
 
 use hal::serial::Read;
-use heapless::consts::*;
 use heapless::spsc::Queue;
 use heapless::String;
 
-#[derive(Debug)]
+/// An error in the input which causes the rest of the line to be skipped.
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum Error{
+    /// The input is not valid UTF-8, see [`Encoding`](enum.Encoding.html).
     InvalidUTF8,
-    InvalidCount,
+    /// A quoted value contains an invalid `\xNN` escape sequence.
+    InvalidEscape,
+    /// The line ends inside of a quoted value.
+    UnterminatedQuote,
+    /// A command, key or value does not fit into the string buffer.
     Overflow
 }
 
-pub struct Tokenizer<SLEN> where SLEN: heapless::ArrayLength<u8> {
-    rb: Queue<u8, U64>,
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            Error::InvalidUTF8 => "Invalid UTF-8",
+            Error::InvalidEscape => "Invalid escape sequence",
+            Error::UnterminatedQuote => "Unterminated quote",
+            Error::Overflow => "Line too long",
+        })
+    }
+}
+
+/// The error returned when filling the input buffer.
+#[derive(Debug)]
+pub enum FillError<E> {
+    /// The serial device returned an error.
+    Serial(E),
+    /// A byte has been received while the input buffer was full and has been
+    /// dropped. Parse the buffered data before trying again.
+    BufferFull,
+}
+
+/// Specifies what happens when bytes are received while the input buffer
+/// is full.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OverrunPolicy {
+    /// Drop the byte which does not fit, stop reading from the serial device
+    /// and return `FillError::BufferFull`. The following bytes are left with
+    /// the serial device.
+    Stop,
+    /// Read and drop the newly received bytes.
+    DropNewest,
+    /// Drop the oldest buffered bytes to make space for the new ones.
+    DropOldest,
+}
+
+/// Specifies how the bytes received are converted into characters.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Encoding {
+    /// Decode the input as UTF-8. Invalid sequences are dropped together
+    /// with the rest of the line and reported as `InvalidUTF8` errors.
+    Utf8,
+    /// Decode the input as UTF-8, substituting invalid sequences with the
+    /// replacement character U+FFFD.
+    Utf8Lossy,
+    /// Treat every byte as a single ISO 8859-1 (Latin-1) character. This 
+    /// includes plain ASCII and never fails.
+    Latin1,
+}
+
+enum Decoded {
+    Char(char, usize),
+    Invalid(usize),
+    Incomplete,
+}
+
+#[derive(Clone, Copy)]
+enum Escape {
+    None,
+    Backslash,
+    Hex(u8, u32),
+}
+
+pub struct Tokenizer<SLEN, QLEN> 
+where SLEN: heapless::ArrayLength<u8>, QLEN: heapless::ArrayLength<u8> {
+    rb: Queue<u8, QLEN>,
     nextstr: String<SLEN>,
+    quote: Option<char>,
+    /// The column at which the current value starts, which for a quoted value
+    /// is the column of its opening quote.
+    value_column: usize,
+    quoted: bool,
+    escape: Escape,
+    discarding: bool,
+    /// The number of characters read on the current line.
+    column: usize,
+    encoding: Encoding,
+    overrun_policy: OverrunPolicy,
+    overruns: usize,
 }
 
 pub enum Token<'a> {
@@ -25,123 +106,347 @@ pub enum Token<'a> {
     CarriageReturn,
     Equals,
     Space,
-    Value(&'a str),
+    /// A command, key or value starting at the given column, counted in 
+    /// characters from one.
+    Value(&'a str, usize),
+    /// The rest of the line is skipped because of an error at the given column,
+    /// counted in characters from one.
+    Error(Error, usize),
 }
 
-impl<SLEN> Tokenizer<SLEN>
-where SLEN: heapless::ArrayLength<u8> {
+impl<SLEN, QLEN> Tokenizer<SLEN, QLEN>
+where SLEN: heapless::ArrayLength<u8>, QLEN: heapless::ArrayLength<u8> {
     pub fn new() -> Self {
         Self {
             rb: Queue::new(),
             nextstr: String::new(),
+            quote: None,
+            value_column: 0,
+            quoted: false,
+            escape: Escape::None,
+            discarding: false,
+            column: 0,
+            encoding: Encoding::Utf8,
+            overrun_policy: OverrunPolicy::Stop,
+            overruns: 0,
         }
     }
 
-    fn get_amount(&mut self, count : usize) -> nb::Result<char, Error> {
-        if count > 4 {
-            Err(nb::Error::Other(Error::InvalidCount))
+    pub fn set_encoding(&mut self, encoding: Encoding) {
+        self.encoding = encoding;
+    }
+
+    pub fn set_overrun_policy(&mut self, policy: OverrunPolicy) {
+        self.overrun_policy = policy;
+    }
+
+    pub fn overruns(&self) -> usize {
+        self.overruns
+    }
+
+    pub fn reset_overruns(&mut self) {
+        self.overruns = 0;
+    }
+
+    /// The number of bytes the buffer can hold.
+    pub fn capacity(&self) -> usize {
+        self.rb.capacity()
+    }
+
+    /// Append a complete line followed by a newline to the buffer.
+    /// 
+    /// # Remarks
+    /// Returns false if there currently is not enough space. A line which is 
+    /// longer than the buffer can never be added, so it is dropped and counted
+    /// as an overrun.
+    pub fn enqueue_line(&mut self, line: &[u8]) -> bool {
+        let len = line.len() + 1;
+
+        if len > self.rb.capacity() {
+            self.overruns = self.overruns.wrapping_add(len);
+            return true
         }
-        else if self.rb.len() < count {
-            Err(nb::Error::WouldBlock)
-        } else {
-            let mut v : u32 = 0;
-
-            for _ in 0..count {
-                let b = self.rb.dequeue().unwrap() as u32;
-
-                if b >> 3 == 0b11110 {
-                    v = (v << 3) | (b & 0b111)
-                } else if b >> 4 == 0b1110 {
-                    v = (v << 4) | (b & 0b1111)
-                } else if b >> 5 == 0b110 {
-                    v = (v << 5) | (b & 0b11111)
-                } else if b >> 6 == 0b10 {
-                    v = (v << 6) | (b & 0b111111)
-                } else {
-                    v = b
-                }
+
+        if len > self.rb.capacity() - self.rb.len() {
+            return false
+        }
+
+        for b in line.iter().chain(b"\n") {
+            // there is enough space for the whole line
+            let _ = self.rb.enqueue(*b);
+        }
+        true
+    }
+
+    /// Decode the UTF-8 sequence at the start of the buffer without 
+    /// consuming it.
+    fn peek_utf8(&self) -> Decoded {
+        let mut bytes = self.rb.iter();
+
+        let b = match bytes.next() {
+            None => return Decoded::Incomplete,
+            Some(&b) => b,
+        };
+
+        // number of bytes, payload of the first byte and the smallest
+        // code point which requires that many bytes
+        let (count, mut v, min) = match b {
+            0x00..=0x7f => return Decoded::Char(char::from(b), 1),
+            0xc2..=0xdf => (2, u32::from(b & 0b11111), 0x80),
+            0xe0..=0xef => (3, u32::from(b & 0b1111), 0x800),
+            0xf0..=0xf4 => (4, u32::from(b & 0b111), 0x10000),
+            // stray continuation bytes or bytes which never appear in UTF-8
+            _ => return Decoded::Invalid(1),
+        };
+
+        for i in 1..count {
+            match bytes.next() {
+                None => return Decoded::Incomplete,
+                Some(&b) if b >> 6 == 0b10 => v = (v << 6) | u32::from(b & 0b111111),
+                // the sequence is cut short, the next byte starts a new character
+                Some(_) => return Decoded::Invalid(i),
             }
-            Ok(core::char::from_u32(v).unwrap())
+        }
+
+        // reject overlong encodings, surrogates and values above U+10FFFF
+        match core::char::from_u32(v) {
+            Some(c) if v >= min => Decoded::Char(c, count),
+            _ => Decoded::Invalid(count),
         }
     }
 
     fn get_char(&mut self) -> nb::Result<char, Error> {
-        if self.rb.is_empty() {
-            Err(nb::Error::WouldBlock)
-        } else {
-            let b = *self.rb.iter().next().unwrap();
-
-            if b >> 3 == 0b11110 as u8 {
-                Ok(self.get_amount(4).unwrap())
-            } else if b >> 4 == 0b1110 as u8 {
-                Ok(self.get_amount(3).unwrap())
-            } else if b >> 5 == 0b110 as u8 {
-                Ok(self.get_amount(2).unwrap())
-            } else if b >> 6 == 0b10 as u8 {
-                Err(nb::Error::Other(Error::InvalidUTF8))
-            } else {
-                Ok(self.rb.dequeue().unwrap() as char)
+        if self.encoding == Encoding::Latin1 {
+            return match self.rb.dequeue() {
+                None => Err(nb::Error::WouldBlock),
+                Some(b) => Ok(char::from(b)),
             }
         }
+
+        let (res, count) = match self.peek_utf8() {
+            Decoded::Incomplete => return Err(nb::Error::WouldBlock),
+            Decoded::Char(c, count) => (Ok(c), count),
+            Decoded::Invalid(count) => match self.encoding {
+                Encoding::Utf8Lossy => (Ok(core::char::REPLACEMENT_CHARACTER), count),
+                _ => (Err(nb::Error::Other(Error::InvalidUTF8)), count),
+            },
+        };
+
+        for _ in 0..count {
+            self.rb.dequeue();
+        }
+
+        res
     } 
 
-    pub fn get_tokens<CB>(&mut self, mut callback : CB) -> nb::Result<(), Error> 
-        where CB: FnMut(Token) -> () {
+    /// Returns true if the next byte in the buffer terminates the line.
+    fn at_line_end(&self) -> bool {
+        matches!(self.rb.iter().next(), Some(&b'\r') | Some(&b'\n'))
+    }
+
+    /// Forget any partially read quoted value.
+    fn reset_quote(&mut self) {
+        self.nextstr = String::new();
+        self.quote = None;
+        self.quoted = false;
+        self.escape = Escape::None;
+    }
+
+    fn push(&mut self, c: char) -> nb::Result<(), Error> {
+        // if we aren't able to push a char onto the string
+        // it probably means it is full 
+        self.nextstr.push(c).map_err(|_| nb::Error::Other(Error::Overflow))
+    }
+
+    /// Handle a character read while inside of a quoted value.
+    fn push_quoted(&mut self, quote: char, c: char) -> nb::Result<(), Error> {
+        match self.escape {
+            Escape::None => match c {
+                '\\' => self.escape = Escape::Backslash,
+                c if c == quote => self.quote = None,
+                c => self.push(c)?,
+            },
+            Escape::Backslash => {
+                self.escape = Escape::None;
+                match c {
+                    'x' => self.escape = Escape::Hex(0, 0),
+                    'n' => self.push('\n')?,
+                    'r' => self.push('\r')?,
+                    't' => self.push('\t')?,
+                    // `\"`, `\'` and `\\` as well as any unknown escapes 
+                    // just yield the escaped character
+                    c => self.push(c)?,
+                }
+            },
+            Escape::Hex(digits, v) => {
+                let v = match c.to_digit(16) {
+                    Some(d) => (v << 4) | d,
+                    None => {
+                        self.escape = Escape::None;
+                        return Err(nb::Error::Other(Error::InvalidEscape))
+                    }
+                };
 
+                if digits == 0 {
+                    self.escape = Escape::Hex(1, v);
+                } else {
+                    self.escape = Escape::None;
+                    self.push(char::from(v as u8))?;
+                }
+            },
+        }
+        Ok(())
+    }
+
+    /// Drop the current line after an error occurred.
+    /// 
+    /// # Remarks
+    /// Everything up to the next line terminator is skipped, after which 
+    /// tokenizing resumes as normal. The lexer is notified using `Token::Error`
+    /// with the column of the error, which for an unterminated quote is the 
+    /// column of the opening quote. All errors except overflows are also passed
+    /// on to the caller.
+    fn recover<CB>(&mut self, res: nb::Result<(), Error>, callback: &mut CB) -> nb::Result<(), Error> 
+        where CB: FnMut(Token) {
+        match res {
+            Err(nb::Error::Other(e)) => {
+                let column = match e {
+                    Error::UnterminatedQuote => self.value_column,
+                    _ => self.column,
+                };
+
+                self.reset_quote();
+                self.discarding = true;
+                callback(Token::Error(e, column));
+
+                match e {
+                    Error::Overflow => Ok(()),
+                    e => Err(nb::Error::Other(e)),
+                }
+            },
+            res => res,
+        }
+    }
+
+    /// Read as many tokens as possible from the buffer.
+    /// 
+    /// # Remarks
+    /// Values may be surrounded by double or single quotes, in which case 
+    /// spaces and `=` are treated as part of the value. A quote only opens a
+    /// value at the start of a word, so that words such as `don't` are kept 
+    /// as they are. Within quotes the 
+    /// escape sequences `\"`, `\'`, `\\`, `\n`, `\r`, `\t` and `\xNN` are 
+    /// recognised. If the line ends before the closing quote, the partial 
+    /// value is dropped and `Error::UnterminatedQuote` is returned, leaving
+    /// the line terminator in the buffer.
+    /// 
+    /// Values which are too long for the string buffer cause the rest of
+    /// the line to be discarded, which is signalled with `Token::Error`.
+    /// Invalid characters or escape sequences also drop the rest of the line 
+    /// and return the corresponding error after signalling it.
+    pub fn get_tokens<CB>(&mut self, mut callback : CB) -> nb::Result<(), Error> 
+        where CB: FnMut(Token) {
 
         loop {
-            let c = match self.get_char() { 
+            if self.quote.is_some() && self.at_line_end() {
+                return self.recover(Err(nb::Error::Other(Error::UnterminatedQuote)), &mut callback)
+            }
+
+            let c = match self.get_char() {
+                Ok(c) => c,
+                Err(nb::Error::WouldBlock) => return Err(nb::Error::WouldBlock),
+                // the line is being dropped anyway
+                Err(nb::Error::Other(_)) if self.discarding => continue,
                 Err(e) => {
-                    return Err(e)
-                },
-                Ok(c) => c
+                    self.column += 1;
+                    self.recover(Err(e), &mut callback)?;
+                    continue;
+                }
             };
+            self.column += 1;
+
+            if self.discarding {
+                match c {
+                    '\r' | '\n' => self.discarding = false,
+                    _ => continue,
+                }
+            }
 
-            let send_val = |callback: &mut CB, s: &mut String<SLEN>| {
-                if !s.is_empty() {
-                    callback(Token::Value(s))
+            if let Some(quote) = self.quote {
+                let res = self.push_quoted(quote, c);
+                self.recover(res, &mut callback)?;
+                continue;
+            }
+
+            let value_column = self.value_column;
+            let send_val = |callback: &mut CB, s: &mut String<SLEN>, quoted: &mut bool| {
+                if !s.is_empty() || *quoted {
+                    callback(Token::Value(s, value_column))
                 }
-                s.clear();
+                *s = String::new();
+                *quoted = false;
             };
 
             match c {
                 ' ' => {
-                    send_val(&mut callback, &mut self.nextstr);
+                    send_val(&mut callback, &mut self.nextstr, &mut self.quoted);
                     callback(Token::Space)
                 },
                 '=' => {
-                    send_val(&mut callback, &mut self.nextstr);
+                    send_val(&mut callback, &mut self.nextstr, &mut self.quoted);
                     callback(Token::Equals)
                 },
                 '\r' => {
-                    send_val(&mut callback, &mut self.nextstr);
+                    send_val(&mut callback, &mut self.nextstr, &mut self.quoted);
+                    self.column = 0;
                     callback(Token::CarriageReturn)
                 }
                 '\n' => {
-                    send_val(&mut callback, &mut self.nextstr);
+                    send_val(&mut callback, &mut self.nextstr, &mut self.quoted);
+                    self.column = 0;
                     callback(Token::NewLine)
                 },
+                // quotes only open a value at its start and are kept within words
+                '"' | '\'' if self.nextstr.is_empty() && !self.quoted => {
+                    self.quote = Some(c);
+                    self.value_column = self.column;
+                    self.quoted = true;
+                },
                 _ => {
-                    match self.nextstr.push(c) {
-                        // if we aren't able to push a char onto the string
-                        // it probably means it is full 
-                        Err(_) => return Err(nb::Error::Other(Error::Overflow)),
-                        _ => ()
+                    if self.nextstr.is_empty() && !self.quoted {
+                        self.value_column = self.column;
                     }
+                    let res = self.push(c);
+                    self.recover(res, &mut callback)?
                 }
             };
 
         }
     }
 
-    pub fn fill<E>(&mut self, ser: &mut Read<u8, Error=E>) -> nb::Result<(), E> {
+    pub fn fill<E>(&mut self, ser: &mut dyn Read<u8, Error=E>) -> nb::Result<(), FillError<E>> {
         loop {
-            let r = ser.read();
-            match r {
+            let full = self.rb.len() == self.rb.capacity();
+
+            let c = match ser.read() {
                 Err(nb::Error::WouldBlock) => return Ok(()),
-                Err(e) => return Err(e),
-                Ok(c) => self.rb.enqueue(c).unwrap()
+                Err(nb::Error::Other(e)) => return Err(nb::Error::Other(FillError::Serial(e))),
+                Ok(c) => c
+            };
+
+            if full {
+                self.overruns = self.overruns.wrapping_add(1);
+
+                match self.overrun_policy {
+                    // leave the remaining bytes with the serial device
+                    OverrunPolicy::Stop => return Err(nb::Error::Other(FillError::BufferFull)),
+                    OverrunPolicy::DropNewest => continue,
+                    OverrunPolicy::DropOldest => { self.rb.dequeue(); },
+                }
             }
+
+            // there is always space at this point
+            let _ = self.rb.enqueue(c);
         }
     }
 }
@@ -0,0 +1,166 @@
+use core::mem;
+
+use heapless::ArrayLength;
+use nb;
+
+use command::{self, KeyError};
+use input::LightCliInput;
+use lexer::CallbackCommand;
+use matching::Matching;
+use output::LightCliOutput;
+use registry::CommandInfo;
+use tokenizer;
+
+/// A type whose values are parsed from the command line by a [`CommandParser`].
+/// 
+/// The trait is usually implemented using `#[derive(LightCommand)]` from the
+/// `light-cli-derive` crate, which is reexported if the `derive` feature is
+/// enabled. Every variant of an enum is a command named after the variant in
+/// upper case and every field is a key named after the field. Fields of type
+/// `Option<T>` are optional and fields of type `bool` are flags, which are 
+/// `false` unless given. All other fields are required. Doc comments on
+/// variants and fields are used as descriptions.
+/// 
+/// # Example
+/// ```ignore
+/// #[derive(LightCommand)]
+/// enum Cmd {
+///     /// Set the name
+///     Hello { name: String<U32> },
+///     /// Print the name
+///     Ehlo,
+///     /// Switch an LED
+///     Led { index: u8, on: bool },
+/// }
+/// ```
+/// 
+/// [`CommandParser`]: struct.CommandParser.html
+pub trait LightCommand: Sized {
+    /// Collects the values of the keys given on the current line.
+    type Builder: Default;
+
+    /// The commands and their keys.
+    const COMMANDS: &'static [CommandInfo];
+
+    /// Parse the value `val` of the key `key` of the command `cmd`.
+    fn on_attribute(builder: &mut Self::Builder, cmd: &str, key: &str, val: &str) -> Result<(), KeyError>;
+
+    /// Parse the word `word` without a value given for the command `cmd`,
+    /// where `index` counts the positional arguments from zero. Flags which 
+    /// are keys of the command do not count towards the index.
+    fn on_argument(builder: &mut Self::Builder, cmd: &str, index: usize, word: &str) -> Result<(), KeyError>;
+
+    /// Create the command `cmd` from the values collected in `builder`, or
+    /// return `None` if the command is unknown or a required key is missing.
+    fn build(builder: Self::Builder, cmd: &str) -> Option<Self>;
+}
+
+/// Parses the input into values of a [`LightCommand`] type and reports errors
+/// to the output in the same way as [`lightcli!`].
+/// 
+/// [`LightCommand`]: trait.LightCommand.html
+/// [`lightcli!`]: macro.lightcli.html
+pub struct CommandParser<C> where C: LightCommand {
+    builder: C::Builder,
+    failed: bool,
+    help_shown: bool,
+}
+
+impl<C> Default for CommandParser<C> where C: LightCommand {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<C> CommandParser<C> where C: LightCommand {
+    /// Create a new parser.
+    pub fn new() -> Self {
+        Self {
+            builder: C::Builder::default(),
+            failed: false,
+            help_shown: false,
+        }
+    }
+
+    /// Parse the buffered data of `cli` and trigger the callback for every
+    /// complete command.
+    /// 
+    /// # Arguments
+    /// * `cli` - The input to parse data from.
+    /// * `out` - The output to pass to the callback and to write errors to.
+    /// * `callback` - The function to call with every command.
+    /// 
+    /// # Remarks
+    /// The commands are passed to [`set_commands`], so that they can be completed
+    /// and their keys are checked. A line with an unknown key or an invalid value
+    /// is reported and the command is not passed on. The command `HELP` is
+    /// answered using the descriptions of the commands.
+    /// 
+    /// [`set_commands`]: struct.LightCliInput.html#method.set_commands
+    pub fn parse<SLEN, QLEN, HLEN, E, OLEN, CB>(&mut self, cli: &mut LightCliInput<SLEN, QLEN, HLEN>,
+        out: &mut LightCliOutput<E, OLEN>, mut callback: CB) -> nb::Result<(), tokenizer::Error>
+        where SLEN: ArrayLength<u8>, QLEN: ArrayLength<u8>,
+            HLEN: ArrayLength<::heapless::Vec<u8, SLEN>>, OLEN: ArrayLength<u8>,
+            CB: FnMut(C, &mut LightCliOutput<E, OLEN>) {
+        cli.set_commands(C::COMMANDS);
+
+        let matching = cli.matching();
+        let res = cli.parse_data(|event| self.handle(out, event, matching, &mut callback));
+
+        let _ = out.show_prompt();
+        res
+    }
+
+    /// Collect a single event and pass on the command at the end of the line.
+    fn handle<E, OLEN, CB>(&mut self, out: &mut LightCliOutput<E, OLEN>, event: CallbackCommand, 
+        matching: Matching, callback: &mut CB)
+        where OLEN: ArrayLength<u8>, CB: FnMut(C, &mut LightCliOutput<E, OLEN>) {
+        let known = |cmd: &str| CommandInfo::find(C::COMMANDS, cmd).is_some();
+
+        let res = match event {
+            CallbackCommand::Argument("HELP", 0, word) if !known("HELP") => {
+                self.help_shown = true;
+                // the command is matched like the commands on the line
+                let _ = match matching.find(C::COMMANDS, |info| info.name, word) {
+                    Ok(Some(info)) => info.write_help(out),
+                    _ => command::write_error(out, &CallbackCommand::Command(word)),
+                };
+                Ok(())
+            },
+            CallbackCommand::Argument("HELP", _, _) if !known("HELP") => Err(KeyError::Unknown),
+            CallbackCommand::Attribute(cmd, key, val) if known(cmd) =>
+                C::on_attribute(&mut self.builder, cmd, key, val),
+            CallbackCommand::Argument(cmd, index, word) if known(cmd) =>
+                C::on_argument(&mut self.builder, cmd, index, word),
+            CallbackCommand::Attribute(..) | CallbackCommand::Argument(..) => Ok(()),
+            _ => {
+                let builder = mem::take(&mut self.builder);
+                let failed = mem::take(&mut self.failed);
+                let help_shown = mem::take(&mut self.help_shown);
+
+                match event {
+                    CallbackCommand::Command("HELP") if !known("HELP") => {
+                        if !help_shown {
+                            let _ = CommandInfo::write_list(C::COMMANDS, out);
+                        }
+                    },
+                    CallbackCommand::Command(cmd) if failed && known(cmd) => (),
+                    CallbackCommand::Command(cmd) => {
+                        match C::build(builder, cmd) {
+                            Some(command) => callback(command, out),
+                            None => { let _ = command::write_error(out, &event); },
+                        }
+                    },
+                    _ => { let _ = command::write_error(out, &event); },
+                }
+                out.request_prompt();
+                return;
+            },
+        };
+
+        if let Err(err) = res {
+            self.failed = true;
+            let _ = command::write_key_error(out, &event, err);
+        }
+    }
+}
@@ -0,0 +1,136 @@
+use heapless::{ArrayLength, String};
+
+/// Converts the value of a key into a typed value for keys declared with a 
+/// type in [`lightcli!`] or [`lightcli_adv!`], e.g. `"Speed": u16 => ...`.
+/// 
+/// Integers may be given in decimal, hexadecimal using `0x` or binary using 
+/// `0b`. Booleans accept `on`, `off`, `true`, `false`, `1` and `0`. Strings 
+/// accept any value which fits into them.
+/// 
+/// # Example
+/// Implement the trait to use your own types, such as enums:
+/// 
+/// ```
+/// # use light_cli::ParseValue;
+/// enum Mode { Fast, Slow }
+/// 
+/// impl ParseValue for Mode {
+///     fn parse_value(s: &str) -> Option<Self> {
+///         match s {
+///             "fast" => Some(Mode::Fast),
+///             "slow" => Some(Mode::Slow),
+///             _ => None,
+///         }
+///     }
+/// }
+/// ```
+/// 
+/// [`lightcli!`]: macro.lightcli.html
+/// [`lightcli_adv!`]: macro.lightcli_adv.html
+pub trait ParseValue: Sized {
+    /// Parse the value, returning `None` if it is invalid.
+    fn parse_value(s: &str) -> Option<Self>;
+
+    /// The value of a key given as a flag without a value, e.g. `RESET force`.
+    /// By default flags are invalid.
+    fn from_flag() -> Option<Self> {
+        None
+    }
+}
+
+/// Split an integer such as `-0x1f` into its sign, digits and radix.
+fn split_int(s: &str) -> Option<(bool, &str, u32)> {
+    let (negative, s) = match s.as_bytes().first() {
+        Some(b'-') => (true, &s[1..]),
+        Some(b'+') => (false, &s[1..]),
+        _ => (false, s),
+    };
+
+    let (digits, radix) = match s.get(..2) {
+        Some("0x") | Some("0X") => (&s[2..], 16),
+        Some("0b") | Some("0B") => (&s[2..], 2),
+        _ => (s, 10),
+    };
+
+    // the digits must not have a sign of their own
+    match digits.as_bytes().first() {
+        Some(b) if b.is_ascii_alphanumeric() => Some((negative, digits, radix)),
+        _ => None,
+    }
+}
+
+macro_rules! impl_parse_unsigned {
+    ($($t:ty),*) => {
+        $(
+        impl ParseValue for $t {
+            fn parse_value(s: &str) -> Option<Self> {
+                match split_int(s)? {
+                    (false, digits, radix) => <$t>::from_str_radix(digits, radix).ok(),
+                    _ => None,
+                }
+            }
+        }
+        )*
+    };
+}
+
+macro_rules! impl_parse_signed {
+    ($($t:ty),*) => {
+        $(
+        impl ParseValue for $t {
+            fn parse_value(s: &str) -> Option<Self> {
+                let (negative, digits, radix) = split_int(s)?;
+                let v = i128::from(u64::from_str_radix(digits, radix).ok()?);
+                let v = if negative { -v } else { v };
+
+                if v < <$t>::MIN as i128 || v > <$t>::MAX as i128 {
+                    None
+                } else {
+                    Some(v as $t)
+                }
+            }
+        }
+        )*
+    };
+}
+
+impl_parse_unsigned!(u8, u16, u32, u64, usize);
+impl_parse_signed!(i8, i16, i32, i64, isize);
+
+impl ParseValue for f32 {
+    fn parse_value(s: &str) -> Option<Self> {
+        s.parse().ok()
+    }
+}
+
+impl ParseValue for f64 {
+    fn parse_value(s: &str) -> Option<Self> {
+        s.parse().ok()
+    }
+}
+
+impl ParseValue for bool {
+    fn parse_value(s: &str) -> Option<Self> {
+        let is = |v: &str| s.eq_ignore_ascii_case(v);
+
+        if is("on") || is("true") || s == "1" {
+            Some(true)
+        } else if is("off") || is("false") || s == "0" {
+            Some(false)
+        } else {
+            None
+        }
+    }
+
+    fn from_flag() -> Option<Self> {
+        Some(true)
+    }
+}
+
+impl<N> ParseValue for String<N> where N: ArrayLength<u8> {
+    fn parse_value(s: &str) -> Option<Self> {
+        let mut value = String::new();
+        value.push_str(s).ok()?;
+        Some(value)
+    }
+}